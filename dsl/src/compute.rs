@@ -5,6 +5,8 @@
 
 use crate::ast::*;
 use crate::parser::Parser;
+use super_c_runtime::ffi::DispatchTarget;
+use super_c_runtime::scheduler::{CostModel, DispatchPolicy, Residency, TaskTarget, Workload};
 
 /// Preferencia de ejecución
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -39,6 +41,8 @@ pub struct ComputeResult {
 pub struct ComputeEngine {
     preference: ComputePreference,
     available_backends: Vec<Backend>,
+    /// Modelo de costo del runtime: lanzamiento + cómputo + copias host/GPU
+    cost_model: CostModel,
 }
 
 impl ComputeEngine {
@@ -46,6 +50,7 @@ impl ComputeEngine {
         Self {
             preference: ComputePreference::Auto,
             available_backends: Self::detect_backends(),
+            cost_model: CostModel::new(),
         }
     }
     
@@ -53,6 +58,7 @@ impl ComputeEngine {
         Self {
             preference,
             available_backends: Self::detect_backends(),
+            cost_model: CostModel::new(),
         }
     }
     
@@ -70,6 +76,13 @@ impl ComputeEngine {
         backends
     }
     
+    /// Reemplaza el modelo de costo, p. ej. uno compartido con el scheduler
+    /// que ya aprendió anchos de banda reales
+    pub fn with_cost_model(mut self, cost_model: CostModel) -> Self {
+        self.cost_model = cost_model;
+        self
+    }
+    
     /// Selecciona el mejor backend según preferencia y disponibilidad
     fn select_backend(&self, workload_size: usize) -> Backend {
         match self.preference {
@@ -86,19 +99,24 @@ impl ComputeEngine {
             ComputePreference::Cpu => Backend::PureCpu,
             ComputePreference::LowPower => Backend::PureCpu,
             ComputePreference::Auto => {
-                // Heurística: GPU para workloads grandes, ASM para medianos, CPU para pequeños
-                if workload_size > 100_000 {
-                    if self.available_backends.contains(&Backend::CudaGpu) {
-                        Backend::CudaGpu
-                    } else if self.available_backends.contains(&Backend::HipGpu) {
-                        Backend::HipGpu
-                    } else {
-                        Backend::AsmSimd
-                    }
-                } else if workload_size > 1000 {
-                    Backend::AsmSimd
-                } else {
-                    Backend::PureCpu
+                // Los arrays del DSL viven en el host: la GPU solo gana si
+                // compensa subir y bajar los datos
+                let bytes = workload_size * std::mem::size_of::<f32>();
+                let workload = Workload {
+                    kind: 0,
+                    target: TaskTarget::Auto,
+                    bytes,
+                    output_bytes: bytes,
+                    residency: Residency::Host,
+                };
+                let gpu = [Backend::CudaGpu, Backend::HipGpu]
+                    .into_iter()
+                    .find(|backend| self.available_backends.contains(backend));
+                let asm = self.available_backends.contains(&Backend::AsmSimd);
+                match self.cost_model.select(&workload, gpu.is_some(), asm) {
+                    DispatchTarget::Gpu => gpu.unwrap_or(Backend::PureCpu),
+                    DispatchTarget::CpuAsm => Backend::AsmSimd,
+                    DispatchTarget::Cpu => Backend::PureCpu,
                 }
             }
         }
@@ -147,6 +147,65 @@ int cuda_launch_kernel(
  */
 int cuda_sync(void);
 
+/* ============================================================================
+ * Rust Arena API (exported by the super_c_runtime cdylib)
+ * ============================================================================ */
+
+/**
+ * Opaque Rust-owned arena
+ */
+typedef struct ScArena ScArena;
+
+/**
+ * Arena usage counters
+ */
+typedef struct {
+    uint64_t allocations;        /* Successful allocations */
+    uint64_t failed_allocations; /* Allocations that did not fit */
+    uint64_t bytes_requested;    /* Bytes asked for by callers */
+    uint64_t bytes_padded;       /* Bytes consumed including padding */
+    uint64_t used;               /* Bytes in use right now */
+    uint64_t peak;               /* High-water mark across resets */
+    uint64_t reserved;           /* Bytes reserved across all chunks */
+    uint64_t resets;             /* Number of resets */
+} ScArenaStats;
+
+/**
+ * Create a fixed-capacity arena
+ * @param capacity Arena size in bytes
+ * @return Arena handle, NULL if capacity is zero or cannot be allocated
+ */
+ScArena* sc_arena_create(size_t capacity);
+
+/**
+ * Destroy an arena created by sc_arena_create
+ * @param arena Arena handle (may be NULL)
+ */
+void sc_arena_destroy(ScArena* arena);
+
+/**
+ * Allocate from an arena (not thread-safe per arena)
+ * @param arena Arena handle
+ * @param size Bytes to allocate
+ * @param align Alignment, a power of two
+ * @return Pointer to arena memory, NULL on failure
+ */
+void* sc_arena_alloc(ScArena* arena, size_t size, size_t align);
+
+/**
+ * Release every allocation in an arena
+ * @param arena Arena handle
+ */
+void sc_arena_reset(ScArena* arena);
+
+/**
+ * Read arena usage counters
+ * @param arena Arena handle
+ * @param out Stats destination
+ * @return SC_SUCCESS on success, SC_ERROR_INVALID on NULL arguments
+ */
+int sc_arena_stats(const ScArena* arena, ScArenaStats* out);
+
 #ifdef __cplusplus
 }
 #endif
@@ -1,39 +1,411 @@
 //! Custom allocator implementations for arena-based memory management
 
-use std::alloc::{GlobalAlloc, Layout};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
 
-/// GPU-compatible allocator handle
-pub struct GpuAllocator {
-    /// Handle to GPU memory (managed by C layer)
-    handle: *mut std::ffi::c_void,
+use super::concurrent::bump_atomic;
+use super::{Arena, BumpAlloc};
+
+/// Aligned allocator for SIMD operations
+///
+/// Hands out from the system allocator by default, or from an arena when
+/// built with `with_arena` (AVX-512, GPU staging, page-sized DMA buffers).
+pub struct AlignedAllocator<'a> {
+    alignment: usize,
+    arena: Option<&'a Arena>,
 }
 
-impl GpuAllocator {
-    /// Create a new GPU allocator (placeholder - actual impl in C/CUDA)
-    pub fn new() -> Self {
+impl AlignedAllocator<'static> {
+    pub fn new(alignment: usize) -> Self {
         Self {
-            handle: std::ptr::null_mut(),
+            alignment,
+            arena: None,
         }
     }
 }
 
-/// Aligned allocator for SIMD operations
-pub struct AlignedAllocator {
+impl<'a> AlignedAllocator<'a> {
+    /// Create an allocator that carves its buffers out of `arena`
+    pub fn with_arena(arena: &'a Arena, alignment: usize) -> Self {
+        Self {
+            alignment,
+            arena: Some(arena),
+        }
+    }
+
+    /// Allocate `size` bytes, null on failure or an invalid layout
+    pub fn alloc(&self, size: usize) -> *mut u8 {
+        let Ok(layout) = Layout::from_size_align(size, self.alignment) else {
+            return std::ptr::null_mut();
+        };
+        match self.arena {
+            Some(arena) => arena
+                .alloc_layout(layout)
+                .map_or(std::ptr::null_mut(), NonNull::as_ptr),
+            None if size == 0 => std::ptr::null_mut(),
+            None => unsafe { std::alloc::alloc(layout) },
+        }
+    }
+
+    /// Release a buffer returned by `alloc` (a no-op for arena-backed buffers)
+    ///
+    /// # Safety
+    /// `ptr` must come from `alloc` on this allocator with the same `size`.
+    pub unsafe fn dealloc(&self, ptr: *mut u8, size: usize) {
+        if self.arena.is_some() {
+            return;
+        }
+        let layout = Layout::from_size_align(size, self.alignment).unwrap();
+        std::alloc::dealloc(ptr, layout)
+    }
+}
+
+/// Types for which the all-zero bit pattern is a valid value
+///
+/// Also the bound for values stored in a `PersistentArena`, which are read
+/// back from whatever bytes the file holds.
+///
+/// # Safety
+/// Implementors must be plain, pointer-free data with no invalid bit patterns.
+pub unsafe trait Zeroable: Copy {}
+
+macro_rules! impl_zeroable {
+    ($($t:ty),*) => { $(unsafe impl Zeroable for $t {})* };
+}
+
+impl_zeroable!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+unsafe impl<T: Zeroable, const N: usize> Zeroable for [T; N] {}
+
+/// Owned, aligned buffer for SIMD and GPU vector ops
+///
+/// Allocated through `AlignedAllocator`; remembers its layout and frees
+/// itself on drop.
+pub struct AlignedBuf<T> {
+    ptr: NonNull<T>,
+    len: usize,
     alignment: usize,
 }
 
-impl AlignedAllocator {
-    pub fn new(alignment: usize) -> Self {
-        Self { alignment }
+unsafe impl<T: Send> Send for AlignedBuf<T> {}
+unsafe impl<T: Sync> Sync for AlignedBuf<T> {}
+
+impl<T: Zeroable> AlignedBuf<T> {
+    /// Allocate `len` zeroed elements aligned to `alignment`
+    pub fn zeroed(len: usize, alignment: usize) -> Option<Self> {
+        let buf = Self::uninit(len, alignment)?;
+        unsafe { std::ptr::write_bytes(buf.ptr.as_ptr(), 0, len) };
+        Some(buf)
     }
+}
 
-    pub fn alloc(&self, size: usize) -> *mut u8 {
-        let layout = Layout::from_size_align(size, self.alignment).unwrap();
-        unsafe { std::alloc::alloc(layout) }
+impl<T: Copy> AlignedBuf<T> {
+    /// Copy `src` into a new buffer aligned to `alignment`
+    pub fn from_slice(src: &[T], alignment: usize) -> Option<Self> {
+        let buf = Self::uninit(src.len(), alignment)?;
+        unsafe { std::ptr::copy_nonoverlapping(src.as_ptr(), buf.ptr.as_ptr(), src.len()) };
+        Some(buf)
     }
 
-    pub fn dealloc(&self, ptr: *mut u8, size: usize) {
-        let layout = Layout::from_size_align(size, self.alignment).unwrap();
-        unsafe { std::alloc::dealloc(ptr, layout) }
+    /// Reserve room for `len` elements; `None` on a bad alignment or size
+    fn uninit(len: usize, alignment: usize) -> Option<Self> {
+        let alignment = alignment.max(std::mem::align_of::<T>());
+        let layout = Layout::array::<T>(len).ok()?.align_to(alignment).ok()?;
+        let ptr = if layout.size() == 0 {
+            NonNull::new(std::ptr::null_mut::<u8>().wrapping_add(alignment))?
+        } else {
+            NonNull::new(AlignedAllocator::new(alignment).alloc(layout.size()))?
+        };
+        Some(Self {
+            ptr: ptr.cast(),
+            len,
+            alignment,
+        })
+    }
+}
+
+impl<T> AlignedBuf<T> {
+    /// Layout the buffer was allocated with
+    pub fn layout(&self) -> Layout {
+        Layout::from_size_align(std::mem::size_of::<T>() * self.len, self.alignment).unwrap()
+    }
+
+    pub fn alignment(&self) -> usize {
+        self.alignment
+    }
+
+    pub fn as_ptr(&self) -> *const T {
+        self.ptr.as_ptr()
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+}
+
+impl<T> Deref for AlignedBuf<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T> DerefMut for AlignedBuf<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T: Copy> Clone for AlignedBuf<T> {
+    fn clone(&self) -> Self {
+        Self::from_slice(self, self.alignment).expect("aligned allocation failed")
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for AlignedBuf<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T> Drop for AlignedBuf<T> {
+    fn drop(&mut self) {
+        let size = self.layout().size();
+        if size > 0 {
+            unsafe {
+                AlignedAllocator::new(self.alignment).dealloc(self.ptr.as_ptr().cast(), size)
+            };
+        }
+    }
+}
+
+thread_local! {
+    /// Nesting depth of `arena_scope` on this thread
+    static ARENA_SCOPE_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Global allocator that serves Rust-side allocations from an arena region
+///
+/// The region is reserved from the system allocator on first use. Freed
+/// memory is reclaimed only when it is the most recent allocation, and
+/// requests that do not fit fall back to the system allocator.
+///
+/// ```no_run
+/// use super_c_runtime::arena::ArenaGlobalAlloc;
+///
+/// #[global_allocator]
+/// static GLOBAL: ArenaGlobalAlloc = ArenaGlobalAlloc::scoped(256 * 1024 * 1024);
+/// # fn main() {}
+/// ```
+pub struct ArenaGlobalAlloc {
+    capacity: usize,
+    /// Only serve allocations made inside `arena_scope`
+    scoped: bool,
+    base: AtomicPtr<u8>,
+    offset: AtomicUsize,
+}
+
+impl ArenaGlobalAlloc {
+    /// Serve every allocation from the arena region
+    pub const fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            scoped: false,
+            base: AtomicPtr::new(std::ptr::null_mut()),
+            offset: AtomicUsize::new(0),
+        }
+    }
+
+    /// Serve only allocations made inside `arena_scope` from the arena region
+    pub const fn scoped(capacity: usize) -> Self {
+        Self {
+            capacity,
+            scoped: true,
+            base: AtomicPtr::new(std::ptr::null_mut()),
+            offset: AtomicUsize::new(0),
+        }
+    }
+
+    /// Bytes currently handed out from the region
+    pub fn used(&self) -> usize {
+        self.offset.load(Ordering::Relaxed)
+    }
+
+    /// Check whether `ptr` was served from the arena region
+    pub fn owns(&self, ptr: *const u8) -> bool {
+        let base = self.base.load(Ordering::Acquire) as usize;
+        base != 0 && (base..base + self.capacity).contains(&(ptr as usize))
+    }
+
+    /// Release every region allocation at once
+    ///
+    /// # Safety
+    /// No allocation served from the region may still be in use.
+    pub unsafe fn reset(&self) {
+        self.offset.store(0, Ordering::Relaxed);
+    }
+
+    fn routed(&self) -> bool {
+        !self.scoped || ARENA_SCOPE_DEPTH.try_with(|d| d.get() > 0).unwrap_or(false)
+    }
+
+    /// Reserve the backing region, racing threads keep the first winner
+    fn region(&self) -> *mut u8 {
+        let base = self.base.load(Ordering::Acquire);
+        if !base.is_null() || self.capacity == 0 {
+            return base;
+        }
+        let layout = self.region_layout();
+        let fresh = unsafe { System.alloc(layout) };
+        if fresh.is_null() {
+            return fresh;
+        }
+        match self.base.compare_exchange(
+            std::ptr::null_mut(),
+            fresh,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => fresh,
+            Err(existing) => {
+                unsafe { System.dealloc(fresh, layout) };
+                existing
+            }
+        }
+    }
+
+    fn bump(&self, layout: Layout) -> *mut u8 {
+        let base = self.region();
+        if base.is_null() {
+            return base;
+        }
+        bump_atomic(base, self.capacity, &self.offset, layout)
+            .map_or(std::ptr::null_mut(), NonNull::as_ptr)
+    }
+
+    fn region_layout(&self) -> Layout {
+        Layout::from_size_align(self.capacity, 4096).unwrap()
+    }
+}
+
+impl Drop for ArenaGlobalAlloc {
+    /// Non-`static` instances give their region back to the system
+    fn drop(&mut self) {
+        let base = *self.base.get_mut();
+        if !base.is_null() {
+            unsafe { System.dealloc(base, self.region_layout()) };
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for ArenaGlobalAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if self.routed() {
+            let ptr = self.bump(layout);
+            if !ptr.is_null() {
+                return ptr;
+            }
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if !self.owns(ptr) {
+            return System.dealloc(ptr, layout);
+        }
+        // Pop the allocation if nothing was bumped after it
+        let start = ptr as usize - self.base.load(Ordering::Relaxed) as usize;
+        let _ = self.offset.compare_exchange(
+            start + layout.size(),
+            start,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        );
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if !self.owns(ptr) {
+            return System.realloc(ptr, layout, new_size);
+        }
+        // Resize in place when this is the most recent allocation
+        let start = ptr as usize - self.base.load(Ordering::Relaxed) as usize;
+        if start + new_size <= self.capacity
+            && self
+                .offset
+                .compare_exchange(
+                    start + layout.size(),
+                    start + new_size,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+        {
+            return ptr;
+        }
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            std::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+}
+
+/// Route this thread's allocations inside `f` to scoped `ArenaGlobalAlloc`s
+pub fn arena_scope<R>(f: impl FnOnce() -> R) -> R {
+    struct Exit;
+
+    impl Drop for Exit {
+        fn drop(&mut self) {
+            ARENA_SCOPE_DEPTH.with(|d| d.set(d.get() - 1));
+        }
+    }
+
+    ARENA_SCOPE_DEPTH.with(|d| d.set(d.get() + 1));
+    let _exit = Exit;
+    f()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aligned_buf() {
+        let mut zeros = AlignedBuf::<f32>::zeroed(100, 64).unwrap();
+        assert_eq!(zeros.as_ptr() as usize % 64, 0);
+        assert!(zeros.iter().all(|&x| x == 0.0));
+        zeros[99] = 1.0;
+
+        let copy = AlignedBuf::from_slice(&zeros, 4096).unwrap();
+        assert_eq!(copy.as_ptr() as usize % 4096, 0);
+        assert_eq!(copy[99], 1.0);
+        assert_eq!(copy.layout().size(), 400);
+        assert!(AlignedBuf::<f32>::zeroed(4, 48).is_none());
+        assert!(AlignedBuf::<f64>::zeroed(0, 64).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_global_alloc_adapter() {
+        let global = ArenaGlobalAlloc::scoped(4096);
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        unsafe {
+            let outside = global.alloc(layout);
+            assert!(!global.owns(outside));
+            global.dealloc(outside, layout);
+
+            let inside = arena_scope(|| global.alloc(layout));
+            assert!(global.owns(inside));
+            let grown = global.realloc(inside, layout, 128);
+            assert_eq!(grown, inside);
+            assert_eq!(global.used(), 128);
+            global.dealloc(grown, Layout::from_size_align(128, 8).unwrap());
+            assert_eq!(global.used(), 0);
+        }
     }
 }
@@ -0,0 +1,164 @@
+//! Arena-backed collections usable on stable Rust
+//!
+//! `std` collections cannot take a custom allocator without nightly, so this
+//! module provides the growable buffer the governor needs directly on top of
+//! any `BumpAlloc`.
+
+use std::alloc::Layout;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+
+use super::{Arena, BumpAlloc};
+
+/// Growable vector whose storage lives in an arena
+///
+/// Growing copies into a fresh arena block; the old block is reclaimed on
+/// the next arena reset. Element destructors run when the vector drops.
+pub struct ArenaVec<'a, T, A: BumpAlloc = Arena> {
+    arena: &'a A,
+    ptr: NonNull<T>,
+    len: usize,
+    cap: usize,
+}
+
+impl<'a, T, A: BumpAlloc> ArenaVec<'a, T, A> {
+    /// Create an empty vector that allocates from `arena`
+    pub fn new_in(arena: &'a A) -> Self {
+        let cap = if std::mem::size_of::<T>() == 0 { usize::MAX } else { 0 };
+        Self {
+            arena,
+            ptr: NonNull::dangling(),
+            len: 0,
+            cap,
+        }
+    }
+
+    /// Create a vector with room for `capacity` elements
+    pub fn with_capacity_in(capacity: usize, arena: &'a A) -> Option<Self> {
+        let mut vec = Self::new_in(arena);
+        vec.reserve(capacity).then_some(vec)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// Append a value, panicking if the arena is exhausted
+    pub fn push(&mut self, value: T) {
+        if self.try_push(value).is_err() {
+            panic!("arena exhausted");
+        }
+    }
+
+    /// Append a value, handing it back if the arena is exhausted
+    pub fn try_push(&mut self, value: T) -> Result<(), T> {
+        if self.len == self.cap && !self.reserve(1) {
+            return Err(value);
+        }
+        unsafe { self.ptr.as_ptr().add(self.len).write(value) };
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(unsafe { self.ptr.as_ptr().add(self.len).read() })
+    }
+
+    pub fn clear(&mut self) {
+        let elems: *mut [T] = &mut **self;
+        self.len = 0;
+        unsafe { std::ptr::drop_in_place(elems) };
+    }
+
+    /// Make room for `additional` more elements
+    pub fn reserve(&mut self, additional: usize) -> bool {
+        let Some(needed) = self.len.checked_add(additional) else {
+            return false;
+        };
+        if needed <= self.cap {
+            return true;
+        }
+        let new_cap = needed.max(self.cap.saturating_mul(2)).max(4);
+        let Some(ptr) = Layout::array::<T>(new_cap)
+            .ok()
+            .and_then(|layout| self.arena.alloc_layout(layout))
+        else {
+            return false;
+        };
+        let ptr = ptr.cast::<T>();
+        unsafe { std::ptr::copy_nonoverlapping(self.ptr.as_ptr(), ptr.as_ptr(), self.len) };
+        self.ptr = ptr;
+        self.cap = new_cap;
+        true
+    }
+
+    /// Leak the elements as a slice that lives as long as the arena borrow
+    pub fn into_slice(self) -> &'a mut [T] {
+        let this = std::mem::ManuallyDrop::new(self);
+        unsafe { std::slice::from_raw_parts_mut(this.ptr.as_ptr(), this.len) }
+    }
+}
+
+impl<T: Clone, A: BumpAlloc> ArenaVec<'_, T, A> {
+    pub fn extend_from_slice(&mut self, src: &[T]) {
+        self.extend(src.iter().cloned());
+    }
+}
+
+impl<T, A: BumpAlloc> Extend<T> for ArenaVec<'_, T, A> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl<T, A: BumpAlloc> Deref for ArenaVec<'_, T, A> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T, A: BumpAlloc> DerefMut for ArenaVec<'_, T, A> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T, A: BumpAlloc> Drop for ArenaVec<'_, T, A> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arena_vec() {
+        let arena = Arena::new(4096);
+        let mut v = ArenaVec::new_in(&arena);
+        v.extend(0..100u32);
+        v.push(100);
+        assert_eq!(v.len(), 101);
+        assert_eq!(v.iter().sum::<u32>(), 5050);
+        assert_eq!(v.pop(), Some(100));
+    }
+}
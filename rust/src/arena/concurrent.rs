@@ -0,0 +1,165 @@
+//! Lock-free arenas shared between scheduler workers
+//!
+//! A `ConcurrentArena` bumps an atomic offset, so any thread can allocate
+//! through `&self`. Workers usually take a `LocalArena`, which grabs batches
+//! from the parent and bumps them without touching shared state.
+
+use std::alloc::Layout;
+use std::cell::Cell;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{ArenaBacking, BumpAlloc, Chunk};
+
+/// Default batch a `LocalArena` takes from its parent (64 KB)
+pub const DEFAULT_BATCH_SIZE: usize = 64 * 1024;
+
+/// Thread-safe bump arena with an atomic offset
+pub struct ConcurrentArena {
+    chunk: Chunk,
+    offset: AtomicUsize,
+}
+
+// Allocations are disjoint ranges handed out by an atomic bump
+unsafe impl Send for ConcurrentArena {}
+unsafe impl Sync for ConcurrentArena {}
+
+impl ConcurrentArena {
+    /// Create a new concurrent arena with given capacity
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            chunk: Chunk::new(capacity, ArenaBacking::Heap, false)
+                .expect("arena allocation failed"),
+            offset: AtomicUsize::new(0),
+        }
+    }
+
+    /// Create a per-thread sub-arena that takes `batch_size` bytes at a time
+    pub fn local(&self, batch_size: usize) -> LocalArena<'_> {
+        LocalArena {
+            parent: self,
+            batch_size,
+            cursor: Cell::new(0),
+            end: Cell::new(0),
+        }
+    }
+
+    /// Reset the arena (free all allocations)
+    ///
+    /// Requires `&mut self`, so no worker can still hold a sub-arena.
+    pub fn reset(&mut self) {
+        *self.offset.get_mut() = 0;
+    }
+
+    /// Get remaining capacity
+    pub fn remaining(&self) -> usize {
+        self.chunk.capacity - self.offset.load(Ordering::Relaxed)
+    }
+}
+
+impl BumpAlloc for ConcurrentArena {
+    fn alloc_layout(&self, layout: Layout) -> Option<NonNull<u8>> {
+        bump_atomic(self.chunk.base, self.chunk.capacity, &self.offset, layout)
+    }
+}
+
+/// Bump `offset` for `layout` within the `capacity` bytes at `base`
+///
+/// Shared with `ArenaGlobalAlloc`, which bumps its region the same way.
+pub(super) fn bump_atomic(
+    base: *mut u8,
+    capacity: usize,
+    offset: &AtomicUsize,
+    layout: Layout,
+) -> Option<NonNull<u8>> {
+    let addr = base as usize;
+    let align = layout.align();
+    let mut current = offset.load(Ordering::Relaxed);
+    loop {
+        let start = (addr + current).checked_add(align - 1)? & !(align - 1);
+        let end = start.checked_add(layout.size())? - addr;
+        if end > capacity {
+            return None;
+        }
+        // Memory is published to other threads through their own
+        // synchronization, so the bump itself only needs atomicity
+        match offset.compare_exchange_weak(current, end, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => return NonNull::new(base.wrapping_add(start - addr)),
+            Err(actual) => current = actual,
+        }
+    }
+}
+
+/// Per-thread sub-arena that refills from a shared `ConcurrentArena`
+pub struct LocalArena<'a> {
+    parent: &'a ConcurrentArena,
+    batch_size: usize,
+    /// Next free address in the current batch
+    cursor: Cell<usize>,
+    /// End address of the current batch
+    end: Cell<usize>,
+}
+
+impl LocalArena<'_> {
+    /// Bytes left in the current batch
+    pub fn remaining(&self) -> usize {
+        self.end.get() - self.cursor.get()
+    }
+
+    fn bump_batch(&self, layout: Layout) -> Option<NonNull<u8>> {
+        let align = layout.align();
+        let start = self.cursor.get().checked_add(align - 1)? & !(align - 1);
+        let end = start.checked_add(layout.size())?;
+        if self.cursor.get() == 0 || end > self.end.get() {
+            return None;
+        }
+        self.cursor.set(end);
+        let base = self.parent.chunk.base;
+        NonNull::new(base.wrapping_add(start - base as usize))
+    }
+}
+
+impl BumpAlloc for LocalArena<'_> {
+    fn alloc_layout(&self, layout: Layout) -> Option<NonNull<u8>> {
+        if let Some(ptr) = self.bump_batch(layout) {
+            return Some(ptr);
+        }
+        // Large requests bypass the batch so they don't waste its tail
+        if layout.size() > self.batch_size / 2 {
+            return self.parent.alloc_layout(layout);
+        }
+        let batch = Layout::from_size_align(self.batch_size, layout.align().max(16)).ok()?;
+        let start = self.parent.alloc_layout(batch)?.as_ptr() as usize;
+        self.cursor.set(start);
+        self.end.set(start + self.batch_size);
+        self.bump_batch(layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parallel_workers() {
+        let arena = ConcurrentArena::new(1024 * 1024);
+        let sums: Vec<u64> = std::thread::scope(|s| {
+            let workers: Vec<_> = (0..4u64)
+                .map(|w| {
+                    let arena = &arena;
+                    s.spawn(move || {
+                        let local = arena.local(4096);
+                        let values: Vec<&mut u64> =
+                            (0..1000).map(|i| local.alloc(w * 1000 + i)).collect();
+                        values.iter().map(|v| **v).sum::<u64>()
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|w| w.join().unwrap()).collect()
+        });
+        for (w, sum) in sums.iter().enumerate() {
+            let w = w as u64;
+            assert_eq!(*sum, (0..1000).map(|i| w * 1000 + i).sum::<u64>());
+        }
+    }
+}
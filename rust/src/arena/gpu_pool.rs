@@ -0,0 +1,322 @@
+//! Suballocating device memory pool
+//!
+//! Device memory is reserved in large blocks and handed out in aligned
+//! pieces, so kernels never pay for a `gpu_malloc` per buffer. Blocks come
+//! from a `DeviceMemory` source: the unified GPU layer (CUDA, HIP or
+//! HIP-CPU) or plain host memory on machines without a GPU.
+
+use std::alloc::Layout;
+use std::collections::BTreeMap;
+use std::ffi::c_void;
+
+use crate::ffi;
+
+/// Default size of a reserved device block (64 MB)
+pub const DEFAULT_GPU_BLOCK_SIZE: usize = 64 * 1024 * 1024;
+
+/// Minimum alignment and size granularity of device sub-allocations
+pub const GPU_MIN_ALIGNMENT: usize = 256;
+
+/// Source of large device memory blocks
+pub trait DeviceMemory {
+    /// Reserve a block of `size` bytes, null on failure
+    fn alloc_block(&mut self, size: usize) -> *mut c_void;
+
+    /// Release a block returned by `alloc_block`
+    ///
+    /// # Safety
+    /// `ptr` and `size` must describe a live block from `alloc_block`.
+    unsafe fn free_block(&mut self, ptr: *mut c_void, size: usize);
+}
+
+/// Device memory from the unified GPU layer (`gpu_malloc` / `gpu_free`)
+pub struct UnifiedGpuMemory;
+
+impl DeviceMemory for UnifiedGpuMemory {
+    fn alloc_block(&mut self, size: usize) -> *mut c_void {
+        unsafe { ffi::gpu_malloc(size) }
+    }
+
+    unsafe fn free_block(&mut self, ptr: *mut c_void, _size: usize) {
+        ffi::gpu_free(ptr)
+    }
+}
+
+/// Host memory stand-in for testing without a GPU
+pub struct HostMemory;
+
+impl HostMemory {
+    fn layout(size: usize) -> Layout {
+        Layout::from_size_align(size, 4096).unwrap()
+    }
+}
+
+impl DeviceMemory for HostMemory {
+    fn alloc_block(&mut self, size: usize) -> *mut c_void {
+        unsafe { std::alloc::alloc(Self::layout(size)).cast() }
+    }
+
+    unsafe fn free_block(&mut self, ptr: *mut c_void, size: usize) {
+        std::alloc::dealloc(ptr.cast(), Self::layout(size))
+    }
+}
+
+/// A sub-allocation handed out by `GpuAllocator`
+///
+/// Move-only: `GpuAllocator::free` consumes it, so it cannot be freed twice.
+#[derive(Debug, PartialEq, Eq)]
+pub struct GpuAllocation {
+    ptr: *mut c_void,
+    size: usize,
+}
+
+impl GpuAllocation {
+    /// Device pointer to pass to kernels and copies
+    pub fn ptr(&self) -> *mut c_void {
+        self.ptr
+    }
+
+    /// Usable size in bytes (rounded up to `GPU_MIN_ALIGNMENT`)
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+/// Why `GpuAllocator::free` rejected an allocation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpuFreeError {
+    /// The allocation does not lie in any block of this pool
+    Unknown,
+    /// Part of the range is already free
+    DoubleFree,
+}
+
+impl std::fmt::Display for GpuFreeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unknown => write!(f, "allocation does not belong to this GpuAllocator"),
+            Self::DoubleFree => write!(f, "allocation is already free"),
+        }
+    }
+}
+
+/// A reserved device block with its free ranges (offset -> length)
+struct Block {
+    size: usize,
+    free: BTreeMap<usize, usize>,
+}
+
+impl Block {
+    fn is_unused(&self) -> bool {
+        self.free.get(&0) == Some(&self.size)
+    }
+
+    /// First-fit search for `size` bytes aligned to `align`
+    fn take(&mut self, base: usize, size: usize, align: usize) -> Option<usize> {
+        let (offset, len, start) = self.free.iter().find_map(|(&offset, &len)| {
+            let start = ((base + offset + align - 1) & !(align - 1)) - base;
+            (start + size <= offset + len).then_some((offset, len, start))
+        })?;
+        self.free.remove(&offset);
+        if start > offset {
+            self.free.insert(offset, start - offset);
+        }
+        if start + size < offset + len {
+            self.free.insert(start + size, offset + len - start - size);
+        }
+        Some(start)
+    }
+
+    /// Return a range to the free list, merging with its neighbours
+    fn give_back(&mut self, mut offset: usize, mut size: usize) -> Result<(), GpuFreeError> {
+        let end = offset.checked_add(size).ok_or(GpuFreeError::Unknown)?;
+        if end > self.size {
+            return Err(GpuFreeError::Unknown);
+        }
+        let prev = self.free.range(..=offset).next_back();
+        let next = self.free.range(offset..).next();
+        if prev.is_some_and(|(&prev, &prev_len)| prev + prev_len > offset)
+            || next.is_some_and(|(&next, _)| next < end)
+        {
+            return Err(GpuFreeError::DoubleFree);
+        }
+        if let Some((&prev, &prev_len)) = self.free.range(..offset).next_back() {
+            if prev + prev_len == offset {
+                self.free.remove(&prev);
+                offset = prev;
+                size += prev_len;
+            }
+        }
+        if let Some(next_len) = self.free.remove(&(offset + size)) {
+            size += next_len;
+        }
+        self.free.insert(offset, size);
+        Ok(())
+    }
+}
+
+/// GPU-compatible allocator handle
+///
+/// Suballocates device memory from large blocks with per-block free lists.
+pub struct GpuAllocator {
+    /// Where device blocks come from (managed by C layer by default)
+    memory: Box<dyn DeviceMemory>,
+    /// Reserved blocks keyed by base address
+    blocks: BTreeMap<usize, Block>,
+    block_size: usize,
+    allocated: usize,
+}
+
+impl GpuAllocator {
+    /// Create a pool on the unified GPU backend
+    pub fn new() -> Self {
+        Self::with_memory(UnifiedGpuMemory, DEFAULT_GPU_BLOCK_SIZE)
+    }
+
+    /// Create a pool that reserves `block_size` blocks from `memory`
+    pub fn with_memory(memory: impl DeviceMemory + 'static, block_size: usize) -> Self {
+        Self {
+            memory: Box::new(memory),
+            blocks: BTreeMap::new(),
+            block_size,
+            allocated: 0,
+        }
+    }
+
+    /// Allocate `size` bytes of device memory aligned to `align`
+    ///
+    /// `align` is raised to `GPU_MIN_ALIGNMENT`; `None` if it is not a power of two.
+    pub fn alloc(&mut self, size: usize, align: usize) -> Option<GpuAllocation> {
+        if !align.is_power_of_two() {
+            return None;
+        }
+        let align = align.max(GPU_MIN_ALIGNMENT);
+        let size = size.max(1).checked_add(GPU_MIN_ALIGNMENT - 1)? & !(GPU_MIN_ALIGNMENT - 1);
+        let found = self
+            .blocks
+            .iter_mut()
+            .find_map(|(&base, block)| Some(base + block.take(base, size, align)?));
+        let addr = match found {
+            Some(addr) => addr,
+            None => {
+                let base = self.reserve(size.checked_add(align - GPU_MIN_ALIGNMENT)?)?;
+                let block = self.blocks.get_mut(&base).unwrap();
+                base + block.take(base, size, align)?
+            }
+        };
+        self.allocated += size;
+        Some(GpuAllocation {
+            ptr: addr as *mut c_void,
+            size,
+        })
+    }
+
+    /// Return a sub-allocation to its block
+    pub fn free(&mut self, allocation: GpuAllocation) -> Result<(), GpuFreeError> {
+        let addr = allocation.ptr as usize;
+        let (&base, block) = self
+            .blocks
+            .range_mut(..=addr)
+            .next_back()
+            .filter(|(&base, block)| addr < base + block.size)
+            .ok_or(GpuFreeError::Unknown)?;
+        block.give_back(addr - base, allocation.size)?;
+        self.allocated -= allocation.size;
+        Ok(())
+    }
+
+    /// Release every block with no live sub-allocations, returning bytes freed
+    pub fn trim(&mut self) -> usize {
+        let unused: Vec<usize> = self
+            .blocks
+            .iter()
+            .filter(|(_, block)| block.is_unused())
+            .map(|(&base, _)| base)
+            .collect();
+        let mut released = 0;
+        for base in unused {
+            let block = self.blocks.remove(&base).unwrap();
+            unsafe { self.memory.free_block(base as *mut c_void, block.size) };
+            released += block.size;
+        }
+        released
+    }
+
+    /// Bytes reserved from the device
+    pub fn reserved(&self) -> usize {
+        self.blocks.values().map(|block| block.size).sum()
+    }
+
+    /// Bytes handed out in live sub-allocations
+    pub fn allocated(&self) -> usize {
+        self.allocated
+    }
+
+    /// Number of reserved device blocks
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Reserve a new block of at least `min_size` bytes
+    fn reserve(&mut self, min_size: usize) -> Option<usize> {
+        let size = self.block_size.max(min_size);
+        let ptr = self.memory.alloc_block(size);
+        if ptr.is_null() {
+            return None;
+        }
+        let mut free = BTreeMap::new();
+        free.insert(0, size);
+        self.blocks.insert(ptr as usize, Block { size, free });
+        Some(ptr as usize)
+    }
+}
+
+impl Default for GpuAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for GpuAllocator {
+    fn drop(&mut self) {
+        for (base, block) in std::mem::take(&mut self.blocks) {
+            unsafe { self.memory.free_block(base as *mut c_void, block.size) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suballocate_coalesce_trim() {
+        let mut pool = GpuAllocator::with_memory(HostMemory, 64 * 1024);
+        let a = pool.alloc(1000, 256).unwrap();
+        let b = pool.alloc(4096, 4096).unwrap();
+        let c = pool.alloc(100, 1).unwrap();
+        assert_eq!(pool.block_count(), 1);
+        assert_eq!(b.ptr() as usize % 4096, 0);
+        assert_eq!(a.size(), 1024);
+        assert!(pool.alloc(64, 384).is_none());
+
+        pool.free(a).unwrap();
+        pool.free(c).unwrap();
+        pool.free(b).unwrap();
+        assert_eq!(pool.allocated(), 0);
+        let whole = pool.alloc(64 * 1024, 256).unwrap();
+        assert_eq!(pool.block_count(), 1);
+
+        // A foreign allocation is rejected without touching the books
+        let mut other = GpuAllocator::with_memory(HostMemory, 64 * 1024);
+        let foreign = other.alloc(256, 256).unwrap();
+        assert_eq!(pool.free(foreign), Err(GpuFreeError::Unknown));
+        assert_eq!(pool.allocated(), 64 * 1024);
+        pool.free(whole).unwrap();
+
+        pool.alloc(128 * 1024, 256).unwrap();
+        assert_eq!(pool.block_count(), 2);
+        assert_eq!(pool.trim(), 64 * 1024);
+        assert_eq!(pool.block_count(), 1);
+    }
+}
@@ -0,0 +1,114 @@
+//! Virtual memory reservation for mmap-backed arenas (Linux)
+//!
+//! The raw `mmap` signature and flag values below are those of 64-bit
+//! x86_64 and aarch64 Linux, so the module is only built there.
+//!
+//! Anonymous mappings are reserved with `MAP_NORESERVE`, so physical pages
+//! are committed lazily on first touch. Transparent huge pages and NUMA
+//! placement are requested per mapping and are best-effort.
+
+use std::ffi::{c_long, c_void};
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: i32,
+        flags: i32,
+        fd: i32,
+        offset: i64,
+    ) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> i32;
+    fn madvise(addr: *mut c_void, len: usize, advice: i32) -> i32;
+    fn msync(addr: *mut c_void, len: usize, flags: i32) -> i32;
+    fn flock(fd: i32, operation: i32) -> i32;
+    fn syscall(number: c_long, ...) -> c_long;
+}
+
+const PROT_READ: i32 = 0x1;
+const PROT_WRITE: i32 = 0x2;
+pub(super) const MAP_SHARED: i32 = 0x01;
+const MAP_PRIVATE: i32 = 0x02;
+const MAP_ANONYMOUS: i32 = 0x20;
+const MAP_NORESERVE: i32 = 0x4000;
+const MADV_HUGEPAGE: i32 = 14;
+const MS_SYNC: i32 = 0x4;
+const LOCK_EX: i32 = 2;
+const LOCK_NB: i32 = 4;
+
+const MPOL_BIND: c_long = 2;
+#[cfg(target_arch = "x86_64")]
+const SYS_MBIND: c_long = 237;
+#[cfg(target_arch = "aarch64")]
+const SYS_MBIND: c_long = 235;
+
+/// Reserve `len` bytes of lazily committed anonymous memory
+pub(super) fn reserve(len: usize, huge_pages: bool, numa_node: Option<u32>) -> Option<*mut u8> {
+    let flags = MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE;
+    let ptr = map(len, flags, -1)?;
+    if huge_pages {
+        unsafe { madvise(ptr.cast(), len, MADV_HUGEPAGE) };
+    }
+    if let Some(node) = numa_node {
+        bind_node(ptr, len, node);
+    }
+    Some(ptr)
+}
+
+/// Map `len` bytes with `flags`, from file descriptor `fd` or anonymously
+pub(super) fn map(len: usize, flags: i32, fd: i32) -> Option<*mut u8> {
+    let ptr = unsafe {
+        mmap(
+            std::ptr::null_mut(),
+            len,
+            PROT_READ | PROT_WRITE,
+            flags,
+            fd,
+            0,
+        )
+    };
+    // MAP_FAILED is (void*)-1
+    (ptr as isize != -1).then_some(ptr.cast())
+}
+
+/// Release a mapping returned by `reserve` or `map`
+///
+/// # Safety
+/// `ptr` and `len` must describe a live mapping that is no longer used.
+pub(super) unsafe fn release(ptr: *mut u8, len: usize) {
+    munmap(ptr.cast(), len);
+}
+
+/// Write dirty pages of a shared mapping back to its file
+///
+/// # Safety
+/// `ptr` and `len` must describe a live mapping.
+pub(super) unsafe fn sync(ptr: *mut u8, len: usize) -> bool {
+    msync(ptr.cast(), len, MS_SYNC) == 0
+}
+
+/// Take an exclusive `flock` on `fd` without blocking
+///
+/// The lock is held until every descriptor sharing the open file is closed.
+pub(super) fn lock_exclusive(fd: i32) -> bool {
+    unsafe { flock(fd, LOCK_EX | LOCK_NB) == 0 }
+}
+
+/// Bind the pages of a mapping to one NUMA node
+fn bind_node(ptr: *mut u8, len: usize, node: u32) {
+    if node < 64 {
+        let mask: u64 = 1 << node;
+        // maxnode counts one past the highest bit the kernel should read
+        unsafe {
+            syscall(
+                SYS_MBIND,
+                ptr.cast::<c_void>(),
+                len,
+                MPOL_BIND,
+                &mask as *const u64,
+                65 as c_long,
+                0 as c_long,
+            )
+        };
+    }
+}
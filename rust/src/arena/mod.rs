@@ -4,56 +4,797 @@
 //! CUDA and ASM never do free malloc - all memory flows through here.
 
 mod allocator;
+mod collections;
+mod concurrent;
+mod gpu_pool;
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod mmap;
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod persistent;
+mod slab;
+mod staging;
 
 pub use allocator::*;
+pub use collections::*;
+pub use concurrent::*;
+pub use gpu_pool::*;
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub use persistent::*;
+pub use slab::*;
+pub use staging::*;
 
-/// Memory arena for controlled allocations
-pub struct Arena {
-    /// Base pointer
+use std::alloc::Layout;
+use std::cell::{Cell, RefCell};
+use std::ops::{Deref, DerefMut};
+use std::panic::Location;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// How a chunked arena sizes new chunks once the current one is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrowthPolicy {
+    /// Never grow; allocations fail once the first chunk is full
+    Fixed,
+    /// Every new chunk has the initial capacity
+    Linear,
+    /// Every new chunk doubles the size of the previous one
+    Doubling,
+}
+
+/// Where arena chunks get their memory from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ArenaBacking {
+    /// `std::alloc` heap memory
+    #[default]
+    Heap,
+    /// Virtual memory reserved with `mmap` and committed on first touch
+    ///
+    /// Linux on x86_64 and aarch64 only; other platforms fall back to `Heap`.
+    Mmap {
+        /// Request transparent huge pages with `madvise`
+        huge_pages: bool,
+        /// Bind pages to this NUMA node (best-effort)
+        numa_node: Option<u32>,
+    },
+}
+
+/// Arena configuration
+#[derive(Debug, Clone, Copy)]
+pub struct ArenaConfig {
+    /// Capacity of the first chunk in bytes
+    pub capacity: usize,
+    /// Growth policy once a chunk is full
+    pub growth: GrowthPolicy,
+    /// Hard upper limit on the bytes reserved across all chunks
+    pub max_capacity: usize,
+    /// Record the call site of every allocation
+    pub trace: bool,
+    /// Debug mode: canary red-zones after allocations, poisoned free memory
+    pub red_zones: bool,
+    /// Memory source for chunks
+    pub backing: ArenaBacking,
+}
+
+impl Default for ArenaConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 64 * 1024 * 1024,
+            growth: GrowthPolicy::Fixed,
+            max_capacity: 1024 * 1024 * 1024,
+            trace: false,
+            red_zones: false,
+            backing: ArenaBacking::Heap,
+        }
+    }
+}
+
+/// Bytes of canary placed after every allocation in red-zone mode
+pub const RED_ZONE_SIZE: usize = 16;
+
+/// Byte pattern written into red-zones
+const CANARY: u8 = 0xFD;
+
+/// Byte pattern written over fresh, freed and reset memory in red-zone mode
+pub const POISON: u8 = 0xDD;
+
+/// A block of memory owned by an arena
+struct Chunk {
     base: *mut u8,
-    /// Current offset
-    offset: usize,
-    /// Total capacity
     capacity: usize,
+    backing: ArenaBacking,
 }
 
-impl Arena {
-    /// Create a new arena with given capacity
-    pub fn new(capacity: usize) -> Self {
-        let layout = std::alloc::Layout::from_size_align(capacity, 16).unwrap();
-        let base = unsafe { std::alloc::alloc(layout) };
-        Self {
+impl Chunk {
+    fn layout(capacity: usize) -> Option<Layout> {
+        Layout::from_size_align(capacity, 16).ok()
+    }
+
+    fn new(capacity: usize, backing: ArenaBacking, poison: bool) -> Option<Self> {
+        let layout = Self::layout(capacity)?;
+        let base = match backing {
+            _ if capacity == 0 => std::ptr::null_mut::<u8>().wrapping_add(16),
+            #[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+            ArenaBacking::Mmap {
+                huge_pages,
+                numa_node,
+            } => mmap::reserve(capacity, huge_pages, numa_node)?,
+            _ => unsafe { std::alloc::alloc(layout) },
+        };
+        if base.is_null() {
+            return None;
+        }
+        let chunk = Self {
             base,
-            offset: 0,
             capacity,
+            backing,
+        };
+        if poison {
+            chunk.poison(0..capacity);
         }
+        Some(chunk)
     }
 
-    /// Allocate bytes from the arena
-    pub fn alloc(&mut self, size: usize) -> Option<*mut u8> {
-        let aligned_size = (size + 15) & !15; // 16-byte alignment
-        if self.offset + aligned_size > self.capacity {
+    fn poison(&self, range: std::ops::Range<usize>) {
+        unsafe { std::ptr::write_bytes(self.base.add(range.start), POISON, range.len()) };
+    }
+}
+
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        match self.backing {
+            _ if self.capacity == 0 => {}
+            #[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+            ArenaBacking::Mmap { .. } => unsafe { mmap::release(self.base, self.capacity) },
+            _ => {
+                let layout = Self::layout(self.capacity).unwrap();
+                unsafe { std::alloc::dealloc(self.base, layout) };
+            }
+        }
+    }
+}
+
+/// Bump allocation shared by every arena type
+///
+/// Typed allocations borrow the allocator, so the borrow checker rejects any
+/// use of them after a reset. Destructors of arena values are never run.
+pub trait BumpAlloc {
+    /// Allocate memory for `layout`, honoring any power-of-two alignment
+    #[track_caller]
+    fn alloc_layout(&self, layout: Layout) -> Option<NonNull<u8>>;
+
+    /// Move a value into the arena, panicking if it is exhausted
+    #[track_caller]
+    #[allow(clippy::mut_from_ref)]
+    fn alloc<T>(&self, value: T) -> &mut T {
+        self.try_alloc(value).expect("arena exhausted")
+    }
+
+    /// Move a value into the arena
+    #[track_caller]
+    #[allow(clippy::mut_from_ref)]
+    fn try_alloc<T>(&self, value: T) -> Option<&mut T> {
+        let ptr = self.alloc_layout(Layout::new::<T>())?.cast::<T>();
+        unsafe {
+            ptr.as_ptr().write(value);
+            Some(&mut *ptr.as_ptr())
+        }
+    }
+
+    /// Allocate `len` default-initialized values, panicking if the arena is exhausted
+    #[track_caller]
+    #[allow(clippy::mut_from_ref)]
+    fn alloc_slice<T: Default>(&self, len: usize) -> &mut [T] {
+        self.try_alloc_slice(len).expect("arena exhausted")
+    }
+
+    /// Allocate `len` default-initialized values
+    #[track_caller]
+    #[allow(clippy::mut_from_ref)]
+    fn try_alloc_slice<T: Default>(&self, len: usize) -> Option<&mut [T]> {
+        let ptr = self.alloc_layout(Layout::array::<T>(len).ok()?)?.cast::<T>();
+        unsafe {
+            for i in 0..len {
+                ptr.as_ptr().add(i).write(T::default());
+            }
+            Some(std::slice::from_raw_parts_mut(ptr.as_ptr(), len))
+        }
+    }
+
+    /// Copy a slice into the arena, panicking if it is exhausted
+    #[track_caller]
+    #[allow(clippy::mut_from_ref)]
+    fn alloc_slice_copy<T: Copy>(&self, src: &[T]) -> &mut [T] {
+        self.try_alloc_slice_copy(src).expect("arena exhausted")
+    }
+
+    /// Copy a slice into the arena
+    #[track_caller]
+    #[allow(clippy::mut_from_ref)]
+    fn try_alloc_slice_copy<T: Copy>(&self, src: &[T]) -> Option<&mut [T]> {
+        let ptr = self.alloc_layout(Layout::array::<T>(src.len()).ok()?)?.cast::<T>();
+        unsafe {
+            std::ptr::copy_nonoverlapping(src.as_ptr(), ptr.as_ptr(), src.len());
+            Some(std::slice::from_raw_parts_mut(ptr.as_ptr(), src.len()))
+        }
+    }
+}
+
+/// Position in an arena that `Arena::rewind` can roll back to
+///
+/// Only valid for the arena that made it, until that arena's next reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    arena: u64,
+    /// Resets the arena had seen when the checkpoint was taken
+    epoch: u64,
+    chunk: usize,
+    offset: usize,
+    used: usize,
+}
+
+/// Snapshot of arena usage counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArenaStats {
+    /// Successful allocations
+    pub allocations: u64,
+    /// Allocations that did not fit
+    pub failed_allocations: u64,
+    /// Bytes asked for by callers
+    pub bytes_requested: u64,
+    /// Bytes consumed including alignment padding
+    pub bytes_padded: u64,
+    /// Bytes in use right now
+    pub used: usize,
+    /// Highest `used` value seen, kept across resets
+    pub peak: usize,
+    /// Bytes reserved across all chunks
+    pub reserved: usize,
+    /// Number of resets
+    pub resets: u64,
+}
+
+/// Red-zone corruption found by `Arena::check_red_zones`
+#[derive(Debug, Clone)]
+pub struct RedZoneViolation {
+    /// Call site of the overrun allocation
+    pub location: &'static Location<'static>,
+    /// Size of the overrun allocation
+    pub size: usize,
+    /// Distance past the end of the allocation of the first corrupted byte
+    pub overrun_at: usize,
+}
+
+impl std::fmt::Display for RedZoneViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "arena allocation of {} bytes at {} overrun: red-zone corrupted {} bytes past its end",
+            self.size, self.location, self.overrun_at
+        )
+    }
+}
+
+/// Allocation record kept while tracing is enabled
+#[derive(Debug, Clone, Copy)]
+pub struct AllocTrace {
+    /// Call site that made the allocation
+    pub location: &'static Location<'static>,
+    pub size: usize,
+    pub align: usize,
+    chunk: usize,
+    offset: usize,
+}
+
+/// Source of `Arena::id`
+static NEXT_ARENA_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Memory arena for controlled allocations
+pub struct Arena {
+    /// Identifies this arena's checkpoints
+    id: u64,
+    /// Base pointer of the current chunk
+    base: Cell<*mut u8>,
+    /// Current offset into the current chunk
+    offset: Cell<usize>,
+    /// Capacity of the current chunk
+    capacity: Cell<usize>,
+    /// All chunks, the current one last
+    chunks: RefCell<Vec<Chunk>>,
+    /// Bytes reserved across all chunks
+    reserved: Cell<usize>,
+    stats: Cell<ArenaStats>,
+    /// Allocation records, present only in trace or red-zone mode
+    trace: Option<RefCell<Vec<AllocTrace>>>,
+    config: ArenaConfig,
+}
+
+impl Arena {
+    /// Create a new fixed-capacity arena
+    ///
+    /// # Panics
+    /// If `capacity` is zero or cannot be allocated.
+    pub fn new(capacity: usize) -> Self {
+        Self::try_new(capacity).expect("arena allocation failed")
+    }
+
+    /// Create a fixed-capacity arena, `None` if `capacity` is zero or cannot be allocated
+    pub fn try_new(capacity: usize) -> Option<Self> {
+        Self::try_with_config(ArenaConfig {
+            capacity,
+            growth: GrowthPolicy::Fixed,
+            max_capacity: capacity,
+            ..ArenaConfig::default()
+        })
+    }
+
+    /// Create an arena that grows according to `config`
+    ///
+    /// The first chunk is clamped to `max_capacity`.
+    ///
+    /// # Panics
+    /// If the clamped capacity is zero or the first chunk cannot be allocated.
+    pub fn with_config(config: ArenaConfig) -> Self {
+        Self::try_with_config(config).expect("arena allocation failed")
+    }
+
+    /// Fallible `with_config`
+    pub fn try_with_config(mut config: ArenaConfig) -> Option<Self> {
+        config.capacity = config.capacity.min(config.max_capacity);
+        if config.capacity == 0 {
             return None;
         }
-        let ptr = unsafe { self.base.add(self.offset) };
-        self.offset += aligned_size;
-        Some(ptr)
+        let chunk = Chunk::new(config.capacity, config.backing, config.red_zones)?;
+        Some(Self {
+            id: NEXT_ARENA_ID.fetch_add(1, Ordering::Relaxed),
+            base: Cell::new(chunk.base),
+            offset: Cell::new(0),
+            capacity: Cell::new(chunk.capacity),
+            chunks: RefCell::new(vec![chunk]),
+            reserved: Cell::new(config.capacity),
+            stats: Cell::new(ArenaStats::default()),
+            trace: (config.trace || config.red_zones).then(|| RefCell::new(Vec::new())),
+            config,
+        })
+    }
+
+    /// Allocate raw bytes from the arena (16-byte aligned)
+    #[track_caller]
+    pub fn alloc_raw(&self, size: usize) -> Option<*mut u8> {
+        self.bump(size, 16).map(NonNull::as_ptr)
     }
 
     /// Reset the arena (free all allocations)
+    ///
+    /// Only the largest chunk is kept, so the next frame rarely has to grow.
+    ///
+    /// # Panics
+    /// In red-zone mode, panics if any allocation overran its red-zone.
     pub fn reset(&mut self) {
-        self.offset = 0;
+        self.assert_red_zones();
+        let chunks = self.chunks.get_mut();
+        if chunks.len() > 1 {
+            let largest = (0..chunks.len())
+                .max_by_key(|&i| chunks[i].capacity)
+                .unwrap();
+            chunks.swap(0, largest);
+            chunks.truncate(1);
+        }
+        if self.config.red_zones {
+            chunks[0].poison(0..chunks[0].capacity);
+        }
+        self.base.set(chunks[0].base);
+        self.capacity.set(chunks[0].capacity);
+        self.reserved.set(chunks[0].capacity);
+        self.offset.set(0);
+        if let Some(trace) = &mut self.trace {
+            trace.get_mut().clear();
+        }
+        let stats = self.stats.get_mut();
+        stats.used = 0;
+        stats.resets += 1;
     }
 
-    /// Get remaining capacity
+    /// Record the current allocation position
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            arena: self.id,
+            epoch: self.stats.get().resets,
+            chunk: self.chunks.borrow().len() - 1,
+            offset: self.offset.get(),
+            used: self.stats.get().used,
+        }
+    }
+
+    /// Roll back every allocation made since `checkpoint`
+    ///
+    /// Chunks added after the checkpoint are released.
+    ///
+    /// # Panics
+    /// If `checkpoint` came from another arena, predates the last reset, or
+    /// lies ahead of the current position.
+    pub fn rewind(&mut self, checkpoint: Checkpoint) {
+        assert!(self.try_rewind(checkpoint), "stale or foreign arena checkpoint");
+    }
+
+    /// Rewind unless `checkpoint` is stale, foreign or ahead of the current position
+    fn try_rewind(&mut self, checkpoint: Checkpoint) -> bool {
+        let chunks = self.chunks.get_mut();
+        let last = chunks.len() - 1;
+        if checkpoint.arena != self.id
+            || checkpoint.epoch != self.stats.get_mut().resets
+            || checkpoint.chunk > last
+            || checkpoint.offset > chunks[checkpoint.chunk].capacity
+            || (checkpoint.chunk == last && checkpoint.offset > self.offset.get())
+        {
+            return false;
+        }
+        for chunk in chunks.drain(checkpoint.chunk + 1..) {
+            self.reserved.set(self.reserved.get() - chunk.capacity);
+        }
+        let current = &chunks[checkpoint.chunk];
+        if self.config.red_zones {
+            let end = if checkpoint.chunk == last {
+                self.offset.get()
+            } else {
+                current.capacity
+            };
+            current.poison(checkpoint.offset..end);
+        }
+        self.base.set(current.base);
+        self.capacity.set(current.capacity);
+        self.offset.set(checkpoint.offset);
+        self.stats.get_mut().used = checkpoint.used;
+        if let Some(trace) = &mut self.trace {
+            trace
+                .get_mut()
+                .retain(|t| (t.chunk, t.offset) < (checkpoint.chunk, checkpoint.offset));
+        }
+        true
+    }
+
+    /// Open a temporary scope that rewinds the arena when dropped
+    pub fn scope(&mut self) -> ArenaScope<'_> {
+        let checkpoint = self.checkpoint();
+        ArenaScope {
+            arena: self,
+            checkpoint,
+        }
+    }
+
+    /// Get remaining capacity in the current chunk
     pub fn remaining(&self) -> usize {
-        self.capacity - self.offset
+        self.capacity.get() - self.offset.get()
+    }
+
+    /// Bytes reserved across all chunks
+    pub fn reserved(&self) -> usize {
+        self.reserved.get()
+    }
+
+    /// Snapshot of the usage counters
+    pub fn stats(&self) -> ArenaStats {
+        ArenaStats {
+            reserved: self.reserved.get(),
+            ..self.stats.get()
+        }
+    }
+
+    /// Verify every red-zone, reporting the first overrun allocation
+    pub fn check_red_zones(&self) -> Result<(), RedZoneViolation> {
+        let (Some(trace), true) = (&self.trace, self.config.red_zones) else {
+            return Ok(());
+        };
+        let chunks = self.chunks.borrow();
+        for record in trace.borrow().iter() {
+            let zone = unsafe {
+                std::slice::from_raw_parts(
+                    chunks[record.chunk].base.add(record.offset + record.size),
+                    RED_ZONE_SIZE,
+                )
+            };
+            if let Some(overrun_at) = zone.iter().position(|&b| b != CANARY) {
+                return Err(RedZoneViolation {
+                    location: record.location,
+                    size: record.size,
+                    overrun_at,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn assert_red_zones(&self) {
+        if let Err(violation) = self.check_red_zones() {
+            panic!("{violation}");
+        }
+    }
+
+    /// Allocation records since the last reset (empty unless tracing)
+    pub fn trace(&self) -> Vec<AllocTrace> {
+        self.trace
+            .as_ref()
+            .map_or_else(Vec::new, |trace| trace.borrow().clone())
+    }
+
+    /// Number of chunks currently owned by the arena
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.borrow().len()
+    }
+
+    /// Bump the offset by `size` bytes, aligning the start address to `align`
+    #[track_caller]
+    fn bump(&self, size: usize, align: usize) -> Option<NonNull<u8>> {
+        debug_assert!(align.is_power_of_two());
+        let mut stats = self.stats.get();
+        let zone = if self.config.red_zones { RED_ZONE_SIZE } else { 0 };
+        let bumped = size.checked_add(zone).and_then(|padded| {
+            self.bump_current(padded, align).or_else(|| {
+                self.grow(padded.checked_add(align - 1)?)?;
+                self.bump_current(padded, align)
+            })
+        });
+        let Some((ptr, consumed)) = bumped else {
+            stats.failed_allocations += 1;
+            self.stats.set(stats);
+            return None;
+        };
+        stats.allocations += 1;
+        stats.bytes_requested += size as u64;
+        stats.bytes_padded += consumed as u64;
+        stats.used += consumed;
+        stats.peak = stats.peak.max(stats.used);
+        self.stats.set(stats);
+        if zone > 0 {
+            unsafe { std::ptr::write_bytes(ptr.as_ptr().add(size), CANARY, zone) };
+        }
+        if let Some(trace) = &self.trace {
+            trace.borrow_mut().push(AllocTrace {
+                location: Location::caller(),
+                size,
+                align,
+                chunk: self.chunks.borrow().len() - 1,
+                offset: ptr.as_ptr() as usize - self.base.get() as usize,
+            });
+        }
+        Some(ptr)
+    }
+
+    /// Bump within the current chunk, returning the pointer and bytes consumed
+    fn bump_current(&self, size: usize, align: usize) -> Option<(NonNull<u8>, usize)> {
+        let base = self.base.get() as usize;
+        let offset = self.offset.get();
+        let start = (base + offset).checked_add(align - 1)? & !(align - 1);
+        let end = start.checked_add(size)?;
+        if end > base + self.capacity.get() {
+            return None;
+        }
+        self.offset.set(end - base);
+        let ptr = NonNull::new(self.base.get().wrapping_add(start - base))?;
+        Some((ptr, end - base - offset))
+    }
+
+    /// Append a chunk large enough for `min_size` bytes, if policy and limit allow
+    fn grow(&self, min_size: usize) -> Option<()> {
+        let size = match self.config.growth {
+            GrowthPolicy::Fixed => return None,
+            GrowthPolicy::Linear => self.config.capacity,
+            GrowthPolicy::Doubling => self.capacity.get().saturating_mul(2),
+        }
+        .max(min_size);
+        let limit = self.config.max_capacity.saturating_sub(self.reserved.get());
+        if min_size > limit {
+            return None;
+        }
+        let chunk = Chunk::new(size.min(limit), self.config.backing, self.config.red_zones)?;
+        self.base.set(chunk.base);
+        self.capacity.set(chunk.capacity);
+        self.offset.set(0);
+        self.reserved.set(self.reserved.get() + chunk.capacity);
+        self.chunks.borrow_mut().push(chunk);
+        Some(())
     }
 }
 
 impl Drop for Arena {
     fn drop(&mut self) {
-        let layout = std::alloc::Layout::from_size_align(self.capacity, 16).unwrap();
-        unsafe { std::alloc::dealloc(self.base, layout) };
+        if !std::thread::panicking() {
+            self.assert_red_zones();
+        }
+    }
+}
+
+impl BumpAlloc for Arena {
+    /// The size is not padded, so consecutive small allocations pack tightly.
+    #[track_caller]
+    fn alloc_layout(&self, layout: Layout) -> Option<NonNull<u8>> {
+        self.bump(layout.size(), layout.align())
+    }
+}
+
+/// RAII guard that rewinds its arena to the scope's start on drop
+pub struct ArenaScope<'a> {
+    arena: &'a mut Arena,
+    checkpoint: Checkpoint,
+}
+
+impl Deref for ArenaScope<'_> {
+    type Target = Arena;
+
+    fn deref(&self) -> &Arena {
+        self.arena
+    }
+}
+
+impl DerefMut for ArenaScope<'_> {
+    fn deref_mut(&mut self) -> &mut Arena {
+        self.arena
+    }
+}
+
+impl Drop for ArenaScope<'_> {
+    fn drop(&mut self) {
+        // The scope may have been reset past its start; nothing to undo then
+        self.arena.try_rewind(self.checkpoint);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_typed_alloc() {
+        let arena = Arena::new(1024);
+        let x = arena.alloc(42u64);
+        let xs = arena.alloc_slice::<f32>(8);
+        let ys = arena.alloc_slice_copy(&[1u8, 2, 3]);
+        *x += 1;
+        xs[7] = 1.5;
+        assert_eq!(*x, 43);
+        assert_eq!(xs, &[0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.5]);
+        assert_eq!(ys, &[1, 2, 3]);
+        assert_eq!(x as *mut u64 as usize % std::mem::align_of::<u64>(), 0);
+    }
+
+    #[test]
+    fn test_exhaustion() {
+        let mut arena = Arena::new(64);
+        assert!(arena.try_alloc_slice::<u8>(65).is_none());
+        assert!(arena.try_alloc([0u8; 64]).is_some());
+        assert!(arena.try_alloc(0u8).is_none());
+        arena.reset();
+        assert_eq!(arena.remaining(), 64);
+    }
+
+    #[test]
+    fn test_alloc_layout_alignment() {
+        let arena = Arena::new(16 * 1024);
+        for align in [64, 256, 4096] {
+            arena.alloc_raw(1).unwrap();
+            let layout = Layout::from_size_align(100, align).unwrap();
+            let ptr = arena.alloc_layout(layout).unwrap();
+            assert_eq!(ptr.as_ptr() as usize % align, 0);
+        }
+    }
+
+    #[test]
+    fn test_chunked_growth() {
+        let mut arena = Arena::with_config(ArenaConfig {
+            capacity: 64,
+            growth: GrowthPolicy::Doubling,
+            max_capacity: 1024,
+            ..ArenaConfig::default()
+        });
+        arena.alloc([0u8; 64]);
+        arena.alloc([0u8; 100]);
+        assert_eq!(arena.chunk_count(), 2);
+        assert_eq!(arena.reserved(), 64 + 128);
+        assert!(arena.try_alloc_slice::<u8>(2048).is_none());
+        arena.reset();
+        assert_eq!(arena.chunk_count(), 1);
+        assert_eq!(arena.remaining(), 128);
+
+        // The first chunk never exceeds the hard limit
+        let arena = Arena::with_config(ArenaConfig {
+            capacity: 4096,
+            growth: GrowthPolicy::Doubling,
+            max_capacity: 256,
+            ..ArenaConfig::default()
+        });
+        assert_eq!(arena.reserved(), 256);
+        assert!(arena.try_alloc_slice::<u8>(257).is_none());
+    }
+
+    #[test]
+    fn test_nested_scopes() {
+        let mut arena = Arena::with_config(ArenaConfig {
+            capacity: 64,
+            growth: GrowthPolicy::Linear,
+            max_capacity: 1024,
+            ..ArenaConfig::default()
+        });
+        arena.alloc(1u32);
+        let start = arena.checkpoint();
+        {
+            let mut outer = arena.scope();
+            outer.alloc([0u8; 32]);
+            {
+                let inner = outer.scope();
+                inner.alloc([0u8; 128]);
+                assert_eq!(inner.chunk_count(), 2);
+            }
+            assert_eq!(outer.chunk_count(), 1);
+        }
+        assert_eq!(arena.checkpoint(), start);
+
+        // Checkpoints from another arena or from before a reset are refused
+        let other = Arena::new(64).checkpoint();
+        let rewind = std::panic::AssertUnwindSafe(|| arena.rewind(other));
+        assert!(std::panic::catch_unwind(rewind).is_err());
+        arena.alloc([0u8; 48]);
+        let stale = arena.checkpoint();
+        arena.reset();
+        arena.alloc([0u8; 60]);
+        assert!(!arena.try_rewind(stale));
+        assert_eq!(arena.remaining(), 4);
+    }
+
+    #[test]
+    fn test_stats_and_trace() {
+        let mut arena = Arena::with_config(ArenaConfig {
+            capacity: 256,
+            growth: GrowthPolicy::Fixed,
+            max_capacity: 256,
+            trace: true,
+            ..ArenaConfig::default()
+        });
+        let line = line!() + 1;
+        arena.alloc(1u8);
+        arena.alloc(2u64);
+        assert!(arena.try_alloc([0u8; 512]).is_none());
+        let stats = arena.stats();
+        assert_eq!(stats.allocations, 2);
+        assert_eq!(stats.failed_allocations, 1);
+        assert_eq!(stats.bytes_requested, 9);
+        assert_eq!(stats.bytes_padded, 16);
+        let trace = arena.trace();
+        assert_eq!(trace.len(), 2);
+        // Records point at the caller, not at the `BumpAlloc` defaults
+        assert_eq!(trace[0].location.file(), file!());
+        assert_eq!(trace[0].location.line(), line);
+        assert_eq!(trace[1].location.line(), line + 1);
+        arena.reset();
+        assert_eq!(arena.stats().peak, 16);
+        assert!(arena.trace().is_empty());
+    }
+
+    #[test]
+    fn test_red_zone_overrun() {
+        let arena = Arena::with_config(ArenaConfig {
+            capacity: 1024,
+            red_zones: true,
+            ..ArenaConfig::default()
+        });
+        let buf = arena.alloc_slice::<u8>(8);
+        assert!(arena.check_red_zones().is_ok());
+        unsafe { buf.as_mut_ptr().add(9).write(0xAA) };
+        let violation = arena.check_red_zones().unwrap_err();
+        assert_eq!((violation.size, violation.overrun_at), (8, 1));
+        unsafe { buf.as_mut_ptr().add(9).write(CANARY) };
+    }
+
+    #[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+    #[test]
+    fn test_mmap_backing() {
+        let arena = Arena::with_config(ArenaConfig {
+            capacity: 8 * 1024 * 1024,
+            backing: ArenaBacking::Mmap {
+                huge_pages: true,
+                numa_node: Some(0),
+            },
+            ..ArenaConfig::default()
+        });
+        let data = arena.alloc_slice_copy(&[7u32; 1024]);
+        assert_eq!(data.iter().sum::<u32>(), 7 * 1024);
     }
 }
@@ -0,0 +1,336 @@
+//! File-backed persistent arena
+//!
+//! The arena lives in a memory-mapped file, so a dataset built in one run
+//! can be reopened in the next without copying. Allocations are addressed
+//! by offset handles instead of raw pointers, which stay valid wherever the
+//! file is mapped. Only plain-old-data values belong in a persistent arena,
+//! so every typed access is bounded on `Zeroable`.
+//!
+//! An arena holds an exclusive `flock` on its file for as long as it lives,
+//! so the same file cannot be mapped twice, from this process or another
+//! one that honours the lock. Writing to the file behind the lock's back
+//! is not supported.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::marker::PhantomData;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use super::{mmap, Zeroable};
+
+/// File format version written into the header
+pub const PERSISTENT_ARENA_VERSION: u32 = 1;
+
+/// Magic bytes at the start of every persistent arena file
+const MAGIC: [u8; 8] = *b"SCARENA\0";
+
+/// Offset of the first allocation; the header sits before it
+pub const PERSISTENT_DATA_OFFSET: usize = 64;
+
+/// On-disk header at offset 0
+#[repr(C)]
+struct Header {
+    magic: [u8; 8],
+    version: u32,
+    _reserved: u32,
+    /// Total file size in bytes
+    capacity: u64,
+    /// Bump offset; everything below it is allocated
+    used: u64,
+}
+
+/// Offset handle to a value in a `PersistentArena`
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct PersistentRef<T> {
+    pub offset: u64,
+    _marker: PhantomData<T>,
+}
+
+/// Offset handle to a slice in a `PersistentArena`
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq)]
+pub struct PersistentSlice<T> {
+    pub offset: u64,
+    pub len: u64,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Clone for PersistentRef<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for PersistentRef<T> {}
+
+impl<T> Clone for PersistentSlice<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for PersistentSlice<T> {}
+
+// Handles are bare offsets, so they can be stored in the arena themselves
+unsafe impl<T> Zeroable for PersistentRef<T> {}
+unsafe impl<T> Zeroable for PersistentSlice<T> {}
+
+impl<T> PersistentRef<T> {
+    /// Rebuild a handle from a stored offset
+    pub fn from_offset(offset: u64) -> Self {
+        Self {
+            offset,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> PersistentSlice<T> {
+    /// Rebuild a handle from a stored offset and length
+    pub fn from_raw_parts(offset: u64, len: u64) -> Self {
+        Self {
+            offset,
+            len,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Bump arena stored in a memory-mapped file
+pub struct PersistentArena {
+    /// Holds the file lock until the mapping is gone
+    _file: File,
+    base: *mut u8,
+    capacity: usize,
+}
+
+impl PersistentArena {
+    /// Create (or truncate) `path` as an empty arena of `capacity` bytes
+    ///
+    /// Fails with `WouldBlock` if another arena has the file open.
+    pub fn create(path: impl AsRef<Path>, capacity: usize) -> io::Result<Self> {
+        if capacity < PERSISTENT_DATA_OFFSET {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "capacity smaller than the arena header",
+            ));
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        // Truncate only once the file is ours
+        lock(&file)?;
+        file.set_len(0)?;
+        file.set_len(capacity as u64)?;
+        let arena = Self::map(file, capacity)?;
+        unsafe {
+            arena.header().write(Header {
+                magic: MAGIC,
+                version: PERSISTENT_ARENA_VERSION,
+                _reserved: 0,
+                capacity: capacity as u64,
+                used: PERSISTENT_DATA_OFFSET as u64,
+            })
+        };
+        Ok(arena)
+    }
+
+    /// Reopen an arena written by an earlier run
+    ///
+    /// Fails with `WouldBlock` if another arena has the file open.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        lock(&file)?;
+        let len = file.metadata()?.len() as usize;
+        if len < PERSISTENT_DATA_OFFSET {
+            return Err(invalid("file too small for an arena header"));
+        }
+        let arena = Self::map(file, len)?;
+        let header = unsafe { &*arena.header() };
+        if header.magic != MAGIC {
+            return Err(invalid("not a persistent arena file"));
+        }
+        if header.version != PERSISTENT_ARENA_VERSION {
+            return Err(invalid("unsupported persistent arena version"));
+        }
+        if header.capacity != len as u64
+            || header.used < PERSISTENT_DATA_OFFSET as u64
+            || header.used > len as u64
+        {
+            return Err(invalid("corrupt persistent arena header"));
+        }
+        Ok(arena)
+    }
+
+    /// Copy a value into the arena
+    pub fn alloc<T: Zeroable>(&mut self, value: T) -> Option<PersistentRef<T>> {
+        let offset = self.bump(std::mem::size_of::<T>(), std::mem::align_of::<T>())?;
+        unsafe { self.base.add(offset).cast::<T>().write(value) };
+        Some(PersistentRef::from_offset(offset as u64))
+    }
+
+    /// Copy a slice into the arena
+    pub fn alloc_slice_copy<T: Zeroable>(&mut self, src: &[T]) -> Option<PersistentSlice<T>> {
+        let size = std::mem::size_of_val(src);
+        let offset = self.bump(size, std::mem::align_of::<T>())?;
+        unsafe {
+            std::ptr::copy_nonoverlapping(src.as_ptr(), self.base.add(offset).cast(), src.len())
+        };
+        Some(PersistentSlice::from_raw_parts(
+            offset as u64,
+            src.len() as u64,
+        ))
+    }
+
+    /// Resolve a value handle; `None` if it is out of bounds or misaligned
+    pub fn get<T: Zeroable>(&self, handle: PersistentRef<T>) -> Option<&T> {
+        let ptr = self.resolve::<T>(handle.offset, 1)?;
+        Some(unsafe { &*ptr })
+    }
+
+    pub fn get_mut<T: Zeroable>(&mut self, handle: PersistentRef<T>) -> Option<&mut T> {
+        let ptr = self.resolve::<T>(handle.offset, 1)?;
+        Some(unsafe { &mut *ptr })
+    }
+
+    /// Resolve a slice handle; `None` if it is out of bounds or misaligned
+    pub fn slice<T: Zeroable>(&self, handle: PersistentSlice<T>) -> Option<&[T]> {
+        let len = usize::try_from(handle.len).ok()?;
+        let ptr = self.resolve::<T>(handle.offset, len)?;
+        Some(unsafe { std::slice::from_raw_parts(ptr, len) })
+    }
+
+    pub fn slice_mut<T: Zeroable>(&mut self, handle: PersistentSlice<T>) -> Option<&mut [T]> {
+        let len = usize::try_from(handle.len).ok()?;
+        let ptr = self.resolve::<T>(handle.offset, len)?;
+        Some(unsafe { std::slice::from_raw_parts_mut(ptr, len) })
+    }
+
+    /// Reset the arena (free all allocations)
+    pub fn reset(&mut self) {
+        self.set_used(PERSISTENT_DATA_OFFSET);
+    }
+
+    /// Bump offset, including the header
+    pub fn used(&self) -> usize {
+        unsafe { (*self.header()).used as usize }
+    }
+
+    /// Total file size in bytes
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Get remaining capacity
+    pub fn remaining(&self) -> usize {
+        self.capacity - self.used()
+    }
+
+    /// Write dirty pages back to the file
+    pub fn flush(&self) -> io::Result<()> {
+        if unsafe { mmap::sync(self.base, self.capacity) } {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    fn map(file: File, len: usize) -> io::Result<Self> {
+        let base = mmap::map(len, mmap::MAP_SHARED, file.as_raw_fd())
+            .ok_or_else(io::Error::last_os_error)?;
+        Ok(Self {
+            _file: file,
+            base,
+            capacity: len,
+        })
+    }
+
+    fn header(&self) -> *mut Header {
+        self.base.cast()
+    }
+
+    fn set_used(&mut self, used: usize) {
+        unsafe { (*self.header()).used = used as u64 };
+    }
+
+    fn bump(&mut self, size: usize, align: usize) -> Option<usize> {
+        let start = self.used().checked_add(align - 1)? & !(align - 1);
+        let end = start.checked_add(size)?;
+        if end > self.capacity {
+            return None;
+        }
+        self.set_used(end);
+        Some(start)
+    }
+
+    /// Bounds- and alignment-check `len` values of `T` at `offset`
+    fn resolve<T>(&self, offset: u64, len: usize) -> Option<*mut T> {
+        let offset = usize::try_from(offset).ok()?;
+        let end = offset.checked_add(std::mem::size_of::<T>().checked_mul(len)?)?;
+        if offset < PERSISTENT_DATA_OFFSET || end > self.used() {
+            return None;
+        }
+        let ptr = self.base.wrapping_add(offset);
+        (ptr as usize)
+            .is_multiple_of(std::mem::align_of::<T>())
+            .then_some(ptr.cast())
+    }
+}
+
+impl Drop for PersistentArena {
+    fn drop(&mut self) {
+        unsafe { mmap::release(self.base, self.capacity) };
+    }
+}
+
+fn lock(file: &File) -> io::Result<()> {
+    if mmap::lock_exclusive(file.as_raw_fd()) {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reopen_dataset() {
+        let path = std::env::temp_dir().join(format!("sc_arena_{}.bin", std::process::id()));
+        let root = {
+            let mut arena = PersistentArena::create(&path, 64 * 1024).unwrap();
+            let samples = arena.alloc_slice_copy(&[1.5f32, 2.5, 3.5]).unwrap();
+            let root = arena.alloc(samples).unwrap();
+            arena.flush().unwrap();
+            root.offset
+        };
+        let arena = PersistentArena::open(&path).unwrap();
+        // The file is locked while mapped, so it cannot be aliased
+        let again = PersistentArena::open(&path).err().unwrap();
+        assert_eq!(again.kind(), io::ErrorKind::WouldBlock);
+        assert!(PersistentArena::create(&path, 4096).is_err());
+        let stored = PersistentRef::<PersistentSlice<f32>>::from_offset(root);
+        let samples = *arena.get(stored).unwrap();
+        assert_eq!(arena.slice(samples), Some(&[1.5f32, 2.5, 3.5][..]));
+        assert!(arena
+            .get(PersistentRef::<u64>::from_offset(1 << 20))
+            .is_none());
+        drop(arena);
+
+        // A header whose bump offset points into the header is rejected
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        std::os::unix::fs::FileExt::write_at(&file, &8u64.to_ne_bytes(), 24).unwrap();
+        assert!(PersistentArena::open(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
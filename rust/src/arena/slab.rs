@@ -0,0 +1,203 @@
+//! Size-class slab pool
+//!
+//! Long-lived objects such as task descriptors and handles need to be freed
+//! one at a time, which a bump `Arena` cannot do. `SlabPool` carves slabs
+//! out of an arena and serves fixed size classes with O(1) alloc and free,
+//! so the memory still flows through Rust arenas.
+
+use std::alloc::Layout;
+use std::cell::Cell;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+
+use super::{Arena, BumpAlloc};
+
+/// Slot sizes served by `SlabPool`, in bytes
+pub const SLAB_SIZE_CLASSES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// Default bytes carved from the arena per slab (64 KB)
+pub const DEFAULT_SLAB_SIZE: usize = 64 * 1024;
+
+/// Usage counters for one size class
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SlabClassStats {
+    /// Slot size in bytes
+    pub slot_size: usize,
+    /// Slots currently handed out
+    pub live: usize,
+    /// Highest `live` value seen
+    pub peak_live: usize,
+    pub allocations: u64,
+    pub frees: u64,
+    /// Slabs carved from the arena for this class
+    pub slabs: usize,
+}
+
+/// Intrusive free-list node stored in an unused slot
+struct FreeSlot {
+    next: Option<NonNull<FreeSlot>>,
+}
+
+/// Free list and bump region of one size class
+struct SizeClass {
+    free: Cell<Option<NonNull<FreeSlot>>>,
+    /// Next never-used slot in the current slab
+    cursor: Cell<usize>,
+    /// End of the current slab
+    end: Cell<usize>,
+    stats: Cell<SlabClassStats>,
+}
+
+/// Fixed size-class allocator backed by an arena
+pub struct SlabPool<'a, A: BumpAlloc = Arena> {
+    arena: &'a A,
+    slab_size: usize,
+    classes: [SizeClass; SLAB_SIZE_CLASSES.len()],
+}
+
+impl<'a, A: BumpAlloc> SlabPool<'a, A> {
+    /// Create a pool that carves `DEFAULT_SLAB_SIZE` slabs from `arena`
+    pub fn new(arena: &'a A) -> Self {
+        Self::with_slab_size(arena, DEFAULT_SLAB_SIZE)
+    }
+
+    /// Create a pool that carves `slab_size` slabs from `arena`
+    pub fn with_slab_size(arena: &'a A, slab_size: usize) -> Self {
+        Self {
+            arena,
+            slab_size: slab_size.max(SLAB_SIZE_CLASSES[SLAB_SIZE_CLASSES.len() - 1]),
+            classes: SLAB_SIZE_CLASSES.map(|slot_size| SizeClass {
+                free: Cell::new(None),
+                cursor: Cell::new(0),
+                end: Cell::new(0),
+                stats: Cell::new(SlabClassStats {
+                    slot_size,
+                    ..SlabClassStats::default()
+                }),
+            }),
+        }
+    }
+
+    /// Allocate a slot for `layout`; `None` if it exceeds every size class
+    pub fn alloc_layout(&self, layout: Layout) -> Option<NonNull<u8>> {
+        let index = Self::class_index(layout)?;
+        let class = &self.classes[index];
+        let ptr = match class.free.get() {
+            Some(slot) => {
+                class.free.set(unsafe { slot.as_ref().next });
+                slot.cast()
+            }
+            None => self.carve(index)?,
+        };
+        let mut stats = class.stats.get();
+        stats.allocations += 1;
+        stats.live += 1;
+        stats.peak_live = stats.peak_live.max(stats.live);
+        class.stats.set(stats);
+        Some(ptr)
+    }
+
+    /// Return a slot to its size class
+    ///
+    /// # Safety
+    /// `ptr` must come from `alloc_layout` on this pool with the same `layout`.
+    pub unsafe fn free(&self, ptr: NonNull<u8>, layout: Layout) {
+        let class = &self.classes[Self::class_index(layout).unwrap()];
+        let slot = ptr.cast::<FreeSlot>();
+        slot.as_ptr().write(FreeSlot {
+            next: class.free.get(),
+        });
+        class.free.set(Some(slot));
+        let mut stats = class.stats.get();
+        stats.frees += 1;
+        stats.live -= 1;
+        class.stats.set(stats);
+    }
+
+    /// Move a value into a slot that is freed when the box drops
+    pub fn alloc<T>(&self, value: T) -> Option<SlabBox<'_, 'a, T, A>> {
+        let ptr = self.alloc_layout(Layout::new::<T>())?.cast::<T>();
+        unsafe { ptr.as_ptr().write(value) };
+        Some(SlabBox { pool: self, ptr })
+    }
+
+    /// Per-class usage counters
+    pub fn class_stats(&self) -> Vec<SlabClassStats> {
+        self.classes.iter().map(|class| class.stats.get()).collect()
+    }
+
+    /// Smallest size class fitting both the size and alignment of `layout`
+    fn class_index(layout: Layout) -> Option<usize> {
+        let needed = layout.size().max(layout.align());
+        SLAB_SIZE_CLASSES.iter().position(|&size| size >= needed)
+    }
+
+    /// Take a never-used slot, starting a new slab when the current one is full
+    fn carve(&self, index: usize) -> Option<NonNull<u8>> {
+        let class = &self.classes[index];
+        let slot_size = SLAB_SIZE_CLASSES[index];
+        if class.cursor.get() + slot_size > class.end.get() {
+            let layout = Layout::from_size_align(self.slab_size, slot_size).ok()?;
+            let slab = self.arena.alloc_layout(layout)?.as_ptr() as usize;
+            class.cursor.set(slab);
+            class.end.set(slab + self.slab_size);
+            let mut stats = class.stats.get();
+            stats.slabs += 1;
+            class.stats.set(stats);
+        }
+        let slot = class.cursor.get();
+        class.cursor.set(slot + slot_size);
+        NonNull::new(slot as *mut u8)
+    }
+}
+
+/// Owned value in a `SlabPool` slot, returned to the pool on drop
+pub struct SlabBox<'p, 'a, T, A: BumpAlloc = Arena> {
+    pool: &'p SlabPool<'a, A>,
+    ptr: NonNull<T>,
+}
+
+impl<T, A: BumpAlloc> Deref for SlabBox<'_, '_, T, A> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T, A: BumpAlloc> DerefMut for SlabBox<'_, '_, T, A> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T, A: BumpAlloc> Drop for SlabBox<'_, '_, T, A> {
+    fn drop(&mut self) {
+        unsafe {
+            std::ptr::drop_in_place(self.ptr.as_ptr());
+            self.pool.free(self.ptr.cast(), Layout::new::<T>());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slot_reuse_and_stats() {
+        let arena = Arena::new(1024 * 1024);
+        let pool = SlabPool::new(&arena);
+        let first = pool.alloc([1u64; 3]).unwrap();
+        let addr = &*first as *const _ as usize;
+        drop(first);
+        let second = pool.alloc([2u64; 4]).unwrap();
+        assert_eq!(&*second as *const _ as usize, addr);
+        assert!(pool.alloc([0u8; 4096]).is_none());
+
+        let stats = pool.class_stats()[1];
+        assert_eq!(stats.slot_size, 32);
+        assert_eq!((stats.allocations, stats.frees, stats.live), (2, 1, 1));
+        assert_eq!(stats.slabs, 1);
+    }
+}
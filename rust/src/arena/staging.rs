@@ -0,0 +1,497 @@
+//! Staging buffers for host/device transfers
+//!
+//! Transfers go through reusable, page-aligned host buffers instead of
+//! whatever memory the caller hands in. Large copies are split into chunks
+//! and double-buffered: one buffer is being copied by the device while the
+//! host fills (or drains) the other on a persistent helper thread.
+//!
+//! Buffers are page-locked with `mlock` on Unix so they stay resident
+//! between transfers. The GPU layer has no host-registration entry point,
+//! so the driver still sees them as pageable memory.
+//!
+//! Each device copy is timed; an attached `TransferObserver` (such as the
+//! scheduler's `CostModel`) hears about every completed transfer.
+
+use std::alloc::Layout;
+use std::ffi::c_void;
+use std::ptr::NonNull;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::HostMemory;
+use crate::ffi;
+
+/// `SC_ERROR_MEMORY` from `super_c.h`: no staging buffer could be allocated
+const SC_ERROR_MEMORY: i32 = -2;
+
+/// Host/device copy routine with the `gpu_memcpy_*` signature
+pub type CopyFn = unsafe extern "C" fn(*mut c_void, *const c_void, usize) -> i32;
+
+/// Receives the measured device-copy time of each completed transfer
+pub trait TransferObserver: Send + Sync {
+    /// `bytes` were copied host to device in `elapsed`
+    fn uploaded(&self, bytes: usize, elapsed: Duration);
+
+    /// `bytes` were copied device to host in `elapsed`
+    fn downloaded(&self, bytes: usize, elapsed: Duration);
+}
+
+/// Source of page-locked host buffers
+pub trait PinnedMemory {
+    /// Allocate a buffer of `size` bytes aligned to `align`, null on failure
+    fn alloc_pinned(&mut self, size: usize, align: usize) -> *mut u8;
+
+    /// Release a buffer returned by `alloc_pinned`
+    ///
+    /// # Safety
+    /// `ptr`, `size` and `align` must describe a live buffer from `alloc_pinned`.
+    unsafe fn free_pinned(&mut self, ptr: *mut u8, size: usize, align: usize);
+}
+
+/// Plain aligned host memory, used when no pinned-memory backend exists
+impl PinnedMemory for HostMemory {
+    fn alloc_pinned(&mut self, size: usize, align: usize) -> *mut u8 {
+        match Layout::from_size_align(size, align) {
+            Ok(layout) => unsafe { std::alloc::alloc(layout) },
+            Err(_) => std::ptr::null_mut(),
+        }
+    }
+
+    unsafe fn free_pinned(&mut self, ptr: *mut u8, size: usize, align: usize) {
+        std::alloc::dealloc(ptr, Layout::from_size_align_unchecked(size, align))
+    }
+}
+
+#[cfg(unix)]
+extern "C" {
+    fn mlock(addr: *const c_void, len: usize) -> i32;
+    fn munlock(addr: *const c_void, len: usize) -> i32;
+}
+
+/// Aligned host memory locked into RAM with `mlock`
+///
+/// Once the `RLIMIT_MEMLOCK` budget is used up, buffers are still handed
+/// out, just unlocked, exactly as `HostMemory` would.
+#[cfg(unix)]
+pub struct LockedMemory;
+
+#[cfg(unix)]
+impl PinnedMemory for LockedMemory {
+    fn alloc_pinned(&mut self, size: usize, align: usize) -> *mut u8 {
+        let ptr = HostMemory.alloc_pinned(size, align);
+        if !ptr.is_null() {
+            unsafe { mlock(ptr.cast(), size) };
+        }
+        ptr
+    }
+
+    unsafe fn free_pinned(&mut self, ptr: *mut u8, size: usize, align: usize) {
+        munlock(ptr.cast(), size);
+        HostMemory.free_pinned(ptr, size, align)
+    }
+}
+
+/// A host-side copy run by the helper thread
+struct HostCopy {
+    dst: *mut u8,
+    src: *const u8,
+    len: usize,
+}
+
+// The transfer that queues a copy waits for it before touching either side
+unsafe impl Send for HostCopy {}
+
+/// Persistent thread that fills or drains the idle staging buffer while
+/// the transferring thread drives the device copy
+struct Helper {
+    jobs: Option<Sender<HostCopy>>,
+    done: Receiver<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Helper {
+    fn spawn() -> Self {
+        let (jobs, queued) = mpsc::channel::<HostCopy>();
+        let (finished, done) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("super-c-staging".into())
+            .spawn(move || {
+                for copy in queued {
+                    unsafe { std::ptr::copy_nonoverlapping(copy.src, copy.dst, copy.len) };
+                    if finished.send(()).is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("failed to spawn staging helper");
+        Self {
+            jobs: Some(jobs),
+            done,
+            thread: Some(thread),
+        }
+    }
+
+    /// Start copying `src` into `dst` in the background
+    ///
+    /// # Safety
+    /// Neither slice may be used or freed until `wait` returns.
+    unsafe fn start(&self, dst: &mut [u8], src: &[u8]) {
+        let copy = HostCopy {
+            dst: dst.as_mut_ptr(),
+            src: src.as_ptr(),
+            len: dst.len().min(src.len()),
+        };
+        self.jobs
+            .as_ref()
+            .unwrap()
+            .send(copy)
+            .expect("staging helper exited");
+    }
+
+    /// Block until the copy queued by `start` has finished
+    fn wait(&self) {
+        self.done.recv().expect("staging helper exited");
+    }
+}
+
+impl Drop for Helper {
+    fn drop(&mut self) {
+        drop(self.jobs.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Staging pool configuration
+#[derive(Debug, Clone, Copy)]
+pub struct StagingConfig {
+    /// Size of each staging buffer, and of each chunk of a large copy
+    pub chunk_size: usize,
+    /// Buffers kept for reuse between transfers
+    pub max_buffers: usize,
+    /// Alignment of staging buffers
+    pub alignment: usize,
+}
+
+impl Default for StagingConfig {
+    fn default() -> Self {
+        Self {
+            chunk_size: 4 * 1024 * 1024, // 4 MB chunks
+            max_buffers: 4,
+            alignment: 4096,
+        }
+    }
+}
+
+/// Pool of reusable host staging buffers for GPU transfers
+pub struct StagingPool {
+    memory: Box<dyn PinnedMemory>,
+    /// Idle buffers, each `chunk_size` bytes
+    free: Vec<NonNull<u8>>,
+    config: StagingConfig,
+    h2d: CopyFn,
+    d2h: CopyFn,
+    /// Started on the first chunked transfer
+    helper: Option<Helper>,
+    observer: Option<Arc<dyn TransferObserver>>,
+}
+
+impl StagingPool {
+    /// Create a pool over the unified GPU copies
+    ///
+    /// Buffers are page-locked on Unix and plain aligned host memory elsewhere.
+    ///
+    /// # Panics
+    /// If `config.chunk_size` is zero.
+    pub fn new(config: StagingConfig) -> Self {
+        #[cfg(unix)]
+        let memory = LockedMemory;
+        #[cfg(not(unix))]
+        let memory = HostMemory;
+        Self::with_backend(memory, ffi::gpu_memcpy_h2d, ffi::gpu_memcpy_d2h, config)
+    }
+
+    /// Create a pool with explicit buffer memory and copy routines
+    ///
+    /// # Panics
+    /// If `config.chunk_size` is zero.
+    pub fn with_backend(
+        memory: impl PinnedMemory + 'static,
+        h2d: CopyFn,
+        d2h: CopyFn,
+        config: StagingConfig,
+    ) -> Self {
+        assert!(config.chunk_size > 0, "staging chunk size must be non-zero");
+        Self {
+            memory: Box::new(memory),
+            free: Vec::new(),
+            config,
+            h2d,
+            d2h,
+            helper: None,
+            observer: None,
+        }
+    }
+
+    /// Report the bandwidth of every completed transfer to `observer`
+    pub fn with_observer(mut self, observer: Arc<dyn TransferObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Number of idle buffers held for reuse
+    pub fn pooled(&self) -> usize {
+        self.free.len()
+    }
+
+    /// Copy `src` to device memory at `dst` through the staging buffers
+    ///
+    /// # Safety
+    /// `dst` must be valid device memory for `src.len()` bytes.
+    pub unsafe fn upload(&mut self, dst: *mut c_void, src: &[u8]) -> Result<(), i32> {
+        let chunk = self.config.chunk_size;
+        let mut front = self.acquire()?;
+        if src.len() <= chunk {
+            let staged = self.buffer(front, src.len());
+            staged.copy_from_slice(src);
+            let result = self.copy(self.h2d, dst, staged.as_ptr().cast(), src.len());
+            self.release(front);
+            return result.map(|elapsed| self.uploaded(src.len(), elapsed));
+        }
+        let mut back = match self.acquire() {
+            Ok(back) => back,
+            Err(code) => {
+                self.release(front);
+                return Err(code);
+            }
+        };
+        self.helper.get_or_insert_with(Helper::spawn);
+        let helper = self.helper.as_ref().unwrap();
+        let pieces: Vec<&[u8]> = src.chunks(chunk).collect();
+        self.buffer(front, pieces[0].len())
+            .copy_from_slice(pieces[0]);
+        let mut elapsed = Duration::ZERO;
+        let mut result = Ok(());
+        for (i, piece) in pieces.iter().enumerate() {
+            let staged = self.buffer(front, piece.len()).as_ptr();
+            // Fill the back buffer while the device copies from the front one
+            let filling = pieces
+                .get(i + 1)
+                .map(|next| helper.start(self.buffer(back, next.len()), next));
+            result = self
+                .copy(
+                    self.h2d,
+                    dst.cast::<u8>().add(i * chunk).cast(),
+                    staged.cast(),
+                    piece.len(),
+                )
+                .map(|copied| elapsed += copied);
+            if filling.is_some() {
+                helper.wait();
+            }
+            if result.is_err() {
+                break;
+            }
+            std::mem::swap(&mut front, &mut back);
+        }
+        self.release(front);
+        self.release(back);
+        result.map(|()| self.uploaded(src.len(), elapsed))
+    }
+
+    /// Copy device memory at `src` into `dst` through the staging buffers
+    ///
+    /// # Safety
+    /// `src` must be valid device memory for `dst.len()` bytes.
+    pub unsafe fn download(&mut self, dst: &mut [u8], src: *const c_void) -> Result<(), i32> {
+        let chunk = self.config.chunk_size;
+        let total = dst.len();
+        let mut front = self.acquire()?;
+        if total <= chunk {
+            let staged = self.buffer(front, dst.len());
+            let result = self.copy(self.d2h, staged.as_mut_ptr().cast(), src, dst.len());
+            if result.is_ok() {
+                dst.copy_from_slice(staged);
+            }
+            self.release(front);
+            return result.map(|elapsed| self.downloaded(dst.len(), elapsed));
+        }
+        let mut back = match self.acquire() {
+            Ok(back) => back,
+            Err(code) => {
+                self.release(front);
+                return Err(code);
+            }
+        };
+        self.helper.get_or_insert_with(Helper::spawn);
+        let helper = self.helper.as_ref().unwrap();
+        let mut pieces: Vec<&mut [u8]> = dst.chunks_mut(chunk).collect();
+        let count = pieces.len();
+        let mut pending: Option<(&[u8], &mut [u8])> = None;
+        let mut elapsed = Duration::ZERO;
+        let mut result = Ok(());
+        for (i, piece) in pieces.drain(..).enumerate() {
+            let staged = self.buffer(front, piece.len());
+            // Drain the previous chunk while the device fills the front buffer
+            let draining = pending.take().map(|(from, to)| helper.start(to, from));
+            result = self
+                .copy(
+                    self.d2h,
+                    staged.as_mut_ptr().cast(),
+                    src.cast::<u8>().add(i * chunk).cast(),
+                    piece.len(),
+                )
+                .map(|copied| elapsed += copied);
+            if draining.is_some() {
+                helper.wait();
+            }
+            if result.is_err() {
+                break;
+            }
+            pending = Some((&*staged, piece));
+            if i + 1 < count {
+                std::mem::swap(&mut front, &mut back);
+            }
+        }
+        if let (Ok(()), Some((from, to))) = (result, pending) {
+            to.copy_from_slice(from);
+        }
+        self.release(front);
+        self.release(back);
+        result.map(|()| self.downloaded(total, elapsed))
+    }
+
+    /// Run one device copy, returning how long it took
+    unsafe fn copy(
+        &self,
+        f: CopyFn,
+        dst: *mut c_void,
+        src: *const c_void,
+        size: usize,
+    ) -> Result<Duration, i32> {
+        let start = Instant::now();
+        match f(dst, src, size) {
+            0 => Ok(start.elapsed()),
+            code => Err(code),
+        }
+    }
+
+    fn uploaded(&self, bytes: usize, elapsed: Duration) {
+        if let Some(observer) = &self.observer {
+            observer.uploaded(bytes, elapsed);
+        }
+    }
+
+    fn downloaded(&self, bytes: usize, elapsed: Duration) {
+        if let Some(observer) = &self.observer {
+            observer.downloaded(bytes, elapsed);
+        }
+    }
+
+    /// View the first `len` bytes of a staging buffer
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn buffer<'b>(&self, ptr: NonNull<u8>, len: usize) -> &'b mut [u8] {
+        std::slice::from_raw_parts_mut(ptr.as_ptr(), len)
+    }
+
+    fn acquire(&mut self) -> Result<NonNull<u8>, i32> {
+        if let Some(ptr) = self.free.pop() {
+            return Ok(ptr);
+        }
+        let ptr = self
+            .memory
+            .alloc_pinned(self.config.chunk_size, self.config.alignment);
+        NonNull::new(ptr).ok_or(SC_ERROR_MEMORY)
+    }
+
+    fn release(&mut self, ptr: NonNull<u8>) {
+        if self.free.len() < self.config.max_buffers {
+            self.free.push(ptr);
+        } else {
+            unsafe {
+                self.memory
+                    .free_pinned(ptr.as_ptr(), self.config.chunk_size, self.config.alignment)
+            };
+        }
+    }
+}
+
+impl Drop for StagingPool {
+    fn drop(&mut self) {
+        for ptr in std::mem::take(&mut self.free) {
+            unsafe {
+                self.memory
+                    .free_pinned(ptr.as_ptr(), self.config.chunk_size, self.config.alignment)
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe extern "C" fn host_copy(dst: *mut c_void, src: *const c_void, size: usize) -> i32 {
+        std::ptr::copy_nonoverlapping(src.cast::<u8>(), dst.cast::<u8>(), size);
+        0
+    }
+
+    #[test]
+    fn test_chunked_round_trip() {
+        let config = StagingConfig {
+            chunk_size: 1024,
+            ..StagingConfig::default()
+        };
+        let mut pool = StagingPool::with_backend(HostMemory, host_copy, host_copy, config);
+        let src: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+        let mut device = vec![0u8; src.len()];
+        let mut back = vec![0u8; src.len()];
+        unsafe {
+            pool.upload(device.as_mut_ptr().cast(), &src).unwrap();
+            pool.download(&mut back, device.as_ptr().cast()).unwrap();
+        }
+        assert_eq!(device, src);
+        assert_eq!(back, src);
+        assert_eq!(pool.pooled(), 2);
+
+        // Completed transfers are reported with their full size
+        #[derive(Default)]
+        struct Totals(std::sync::Mutex<(usize, usize)>);
+        impl TransferObserver for Totals {
+            fn uploaded(&self, bytes: usize, _: Duration) {
+                self.0.lock().unwrap().0 += bytes;
+            }
+            fn downloaded(&self, bytes: usize, _: Duration) {
+                self.0.lock().unwrap().1 += bytes;
+            }
+        }
+        let totals = Arc::new(Totals::default());
+        let mut pool = pool.with_observer(totals.clone());
+        unsafe {
+            pool.upload(device.as_mut_ptr().cast(), &src).unwrap();
+            pool.upload(device.as_mut_ptr().cast(), &src[..100]).unwrap();
+            pool.download(&mut back, device.as_ptr().cast()).unwrap();
+        }
+        assert_eq!(*totals.0.lock().unwrap(), (5100, 5000));
+
+        #[cfg(unix)]
+        {
+            let mut locked = StagingPool::with_backend(LockedMemory, host_copy, host_copy, config);
+            let mut device = vec![0u8; src.len()];
+            unsafe { locked.upload(device.as_mut_ptr().cast(), &src).unwrap() };
+            assert_eq!(device, src);
+        }
+
+        let empty = StagingConfig {
+            chunk_size: 0,
+            ..StagingConfig::default()
+        };
+        assert!(std::panic::catch_unwind(|| {
+            StagingPool::with_backend(HostMemory, host_copy, host_copy, empty)
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,134 @@
+//! Arena C ABI
+//!
+//! Exported from the `super_c_runtime` cdylib so C/CUDA/ASM code can take
+//! scratch memory from Rust arenas instead of calling malloc.
+//! Declarations live in `native/include/super_c.h`.
+
+use std::alloc::Layout;
+use std::ffi::c_void;
+
+use crate::arena::{Arena, ArenaStats, BumpAlloc};
+
+/// Status codes shared with `super_c.h`
+const SC_SUCCESS: i32 = 0;
+const SC_ERROR_INVALID: i32 = -3;
+
+/// Arena usage counters as seen from C (`ScArenaStats`)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScArenaStats {
+    pub allocations: u64,
+    pub failed_allocations: u64,
+    pub bytes_requested: u64,
+    pub bytes_padded: u64,
+    pub used: u64,
+    pub peak: u64,
+    pub reserved: u64,
+    pub resets: u64,
+}
+
+impl From<ArenaStats> for ScArenaStats {
+    fn from(stats: ArenaStats) -> Self {
+        Self {
+            allocations: stats.allocations,
+            failed_allocations: stats.failed_allocations,
+            bytes_requested: stats.bytes_requested,
+            bytes_padded: stats.bytes_padded,
+            used: stats.used as u64,
+            peak: stats.peak as u64,
+            reserved: stats.reserved as u64,
+            resets: stats.resets,
+        }
+    }
+}
+
+/// Create a fixed-capacity arena owned by the caller, NULL if `capacity`
+/// is zero or cannot be allocated
+#[no_mangle]
+pub extern "C" fn sc_arena_create(capacity: usize) -> *mut Arena {
+    Arena::try_new(capacity).map_or(std::ptr::null_mut(), |arena| Box::into_raw(Box::new(arena)))
+}
+
+/// Destroy an arena created by `sc_arena_create`
+///
+/// # Safety
+/// `arena` must come from `sc_arena_create` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn sc_arena_destroy(arena: *mut Arena) {
+    if !arena.is_null() {
+        drop(Box::from_raw(arena));
+    }
+}
+
+/// Allocate `size` bytes aligned to `align` (a power of two), NULL on failure
+///
+/// # Safety
+/// `arena` must be a live arena, used by one thread at a time.
+#[no_mangle]
+pub unsafe extern "C" fn sc_arena_alloc(
+    arena: *mut Arena,
+    size: usize,
+    align: usize,
+) -> *mut c_void {
+    let (Some(arena), Ok(layout)) = (arena.as_ref(), Layout::from_size_align(size, align)) else {
+        return std::ptr::null_mut();
+    };
+    arena
+        .alloc_layout(layout)
+        .map_or(std::ptr::null_mut(), |ptr| ptr.as_ptr().cast())
+}
+
+/// Release every allocation in the arena
+///
+/// # Safety
+/// `arena` must be a live arena with no outstanding Rust borrows.
+#[no_mangle]
+pub unsafe extern "C" fn sc_arena_reset(arena: *mut Arena) {
+    if let Some(arena) = arena.as_mut() {
+        arena.reset();
+    }
+}
+
+/// Write the arena's usage counters to `out`
+///
+/// # Safety
+/// `arena` must be a live arena and `out` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn sc_arena_stats(arena: *const Arena, out: *mut ScArenaStats) -> i32 {
+    match (arena.as_ref(), out.is_null()) {
+        (Some(arena), false) => {
+            out.write(arena.stats().into());
+            SC_SUCCESS
+        }
+        _ => SC_ERROR_INVALID,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_c_abi_round_trip() {
+        unsafe {
+            let arena = sc_arena_create(4096);
+            let ptr = sc_arena_alloc(arena, 100, 64);
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % 64, 0);
+            assert!(sc_arena_alloc(arena, 8, 3).is_null());
+
+            let mut stats = ScArenaStats::default();
+            assert_eq!(sc_arena_stats(arena, &mut stats), SC_SUCCESS);
+            assert_eq!((stats.allocations, stats.bytes_requested), (1, 100));
+            sc_arena_reset(arena);
+            assert_eq!(sc_arena_stats(arena, &mut stats), SC_SUCCESS);
+            assert_eq!((stats.used, stats.resets), (0, 1));
+            sc_arena_destroy(arena);
+
+            // Never panics across the C boundary
+            assert!(sc_arena_create(0).is_null());
+            assert!(sc_arena_create(usize::MAX).is_null());
+            assert!(sc_arena_create(isize::MAX as usize - 4096).is_null());
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Generational handle table
+//!
+//! Native resources (GPU buffers, streams, kernels) are referenced by a
+//! slot index plus a generation counter instead of a bare pointer. Freeing
+//! a resource bumps its slot's generation, so a stale handle is reported as
+//! an error instead of reaching native code as a dangling pointer.
+
+use std::fmt;
+
+use crate::contracts::ContractViolation;
+
+/// Reference to a resource in a `HandleTable`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Handle {
+    pub index: u32,
+    pub generation: u32,
+}
+
+impl Handle {
+    /// Handle that never resolves (generations start at 1)
+    pub const NULL: Handle = Handle {
+        index: 0,
+        generation: 0,
+    };
+
+    pub fn is_null(&self) -> bool {
+        self.generation == 0
+    }
+}
+
+/// Why a handle failed to resolve
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandleError {
+    /// The slot was freed (and possibly reused) since the handle was issued
+    Stale { handle: Handle, current: u32 },
+    /// The index was never issued by this table
+    Invalid(Handle),
+}
+
+impl fmt::Display for HandleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandleError::Stale { handle, current } => write!(
+                f,
+                "stale handle {}:{} (slot is now at generation {})",
+                handle.index, handle.generation, current
+            ),
+            HandleError::Invalid(handle) => {
+                write!(f, "invalid handle {}:{}", handle.index, handle.generation)
+            }
+        }
+    }
+}
+
+impl From<HandleError> for ContractViolation {
+    fn from(error: HandleError) -> Self {
+        let message = match error {
+            HandleError::Stale { .. } => "use of a freed native resource",
+            HandleError::Invalid(_) => "handle was never issued",
+        };
+        ContractViolation {
+            message,
+            location: "ffi::HandleTable",
+        }
+    }
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+/// Slot map from generational handles to resources
+pub struct HandleTable<T> {
+    slots: Vec<Slot<T>>,
+    /// Indices of empty slots, reused LIFO
+    free: Vec<u32>,
+}
+
+impl<T> HandleTable<T> {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    /// Store a resource and issue a handle for it
+    pub fn insert(&mut self, value: T) -> Handle {
+        if let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[index as usize];
+            slot.value = Some(value);
+            return Handle {
+                index,
+                generation: slot.generation,
+            };
+        }
+        let index = u32::try_from(self.slots.len()).expect("handle table full");
+        self.slots.push(Slot {
+            generation: 1,
+            value: Some(value),
+        });
+        Handle {
+            index,
+            generation: 1,
+        }
+    }
+
+    pub fn get(&self, handle: Handle) -> Result<&T, HandleError> {
+        let slot = self.slot(handle)?;
+        Ok(slot.value.as_ref().unwrap())
+    }
+
+    pub fn get_mut(&mut self, handle: Handle) -> Result<&mut T, HandleError> {
+        self.slot(handle)?;
+        Ok(self.slots[handle.index as usize].value.as_mut().unwrap())
+    }
+
+    /// Take the resource out, invalidating every copy of `handle`
+    pub fn remove(&mut self, handle: Handle) -> Result<T, HandleError> {
+        self.slot(handle)?;
+        let slot = &mut self.slots[handle.index as usize];
+        // Skip generation 0 so a wrapped counter never matches Handle::NULL
+        slot.generation = slot.generation.wrapping_add(1).max(1);
+        self.free.push(handle.index);
+        Ok(slot.value.take().unwrap())
+    }
+
+    pub fn contains(&self, handle: Handle) -> bool {
+        self.slot(handle).is_ok()
+    }
+
+    /// Number of live resources
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterate over live handles and their resources
+    pub fn iter(&self) -> impl Iterator<Item = (Handle, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let handle = Handle {
+                index: index as u32,
+                generation: slot.generation,
+            };
+            slot.value.as_ref().map(|value| (handle, value))
+        })
+    }
+
+    fn slot(&self, handle: Handle) -> Result<&Slot<T>, HandleError> {
+        let slot = self
+            .slots
+            .get(handle.index as usize)
+            .filter(|_| !handle.is_null())
+            .ok_or(HandleError::Invalid(handle))?;
+        if slot.generation != handle.generation || slot.value.is_none() {
+            return Err(HandleError::Stale {
+                handle,
+                current: slot.generation,
+            });
+        }
+        Ok(slot)
+    }
+}
+
+impl<T> Default for HandleTable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stale_handles() {
+        let mut table = HandleTable::new();
+        let buffer = table.insert("gpu buffer");
+        assert_eq!(table.get(buffer), Ok(&"gpu buffer"));
+        assert_eq!(table.remove(buffer), Ok("gpu buffer"));
+
+        let stream = table.insert("stream");
+        assert_eq!(stream.index, buffer.index);
+        assert_eq!(
+            table.get(buffer),
+            Err(HandleError::Stale {
+                handle: buffer,
+                current: 2
+            })
+        );
+        assert!(table.remove(buffer).is_err());
+        assert!(table.get(Handle::NULL).is_err());
+        assert_eq!(table.len(), 1);
+    }
+}
@@ -100,6 +100,15 @@ extern "C" {
     /// Synchronize
     pub fn gpu_sync() -> i32;
 
+    /// Launch kernel on the active backend
+    pub fn gpu_launch_kernel(
+        kernel_id: u32,
+        input: *const c_void,
+        input_size: usize,
+        output: *mut c_void,
+        output_size: *mut usize,
+    ) -> i32;
+
     /// Vector add
     pub fn gpu_vector_add_f32(a: *const f32, b: *const f32, c: *mut f32, n: usize) -> i32;
 
@@ -6,14 +6,21 @@
 mod native;
 mod cuda;
 mod hip;
+mod exports;
+mod handles;
 
 pub use native::*;
 pub use cuda::*;
 pub use hip::*;
+pub use exports::*;
+pub use handles::*;
 
 use std::ffi::c_void;
 
 /// Opaque handle to native resources
+///
+/// Store these in a `HandleTable` and pass generational `Handle`s around,
+/// so stale references are caught before they reach native code.
 #[repr(C)]
 pub struct NativeHandle {
     ptr: *mut c_void,
@@ -24,6 +31,15 @@ impl NativeHandle {
         Self { ptr: std::ptr::null_mut() }
     }
 
+    /// Wrap a pointer returned by native code
+    pub fn from_raw(ptr: *mut c_void) -> Self {
+        Self { ptr }
+    }
+
+    pub fn as_ptr(&self) -> *mut c_void {
+        self.ptr
+    }
+
     pub fn is_null(&self) -> bool {
         self.ptr.is_null()
     }
@@ -31,7 +47,7 @@ impl NativeHandle {
 
 /// Dispatch target for execution
 #[repr(C)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum DispatchTarget {
     /// Execute on CPU (C code)
     Cpu = 0,
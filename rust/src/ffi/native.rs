@@ -5,7 +5,7 @@
 
 use std::ffi::c_void;
 
-/// External C functions (implemented in native/ layer)
+// External C functions (implemented in native/ layer)
 extern "C" {
     /// Initialize native runtime
     pub fn native_init() -> i32;
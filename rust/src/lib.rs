@@ -20,6 +20,12 @@ pub struct RuntimeConfig {
     pub asm_enabled: bool,
     /// Arena size in bytes
     pub arena_size: usize,
+    /// Arena growth once `arena_size` is used up
+    pub arena_growth: arena::GrowthPolicy,
+    /// Hard upper limit on arena memory in bytes
+    pub arena_max_size: usize,
+    /// Arena backing store (`std::alloc` heap by default)
+    pub arena_backing: arena::ArenaBacking,
 }
 
 impl Default for RuntimeConfig {
@@ -28,12 +34,32 @@ impl Default for RuntimeConfig {
             cuda_enabled: cfg!(feature = "cuda"),
             asm_enabled: cfg!(feature = "asm"),
             arena_size: 64 * 1024 * 1024, // 64 MB default
+            arena_growth: arena::GrowthPolicy::Fixed,
+            arena_max_size: 1024 * 1024 * 1024, // 1 GB limit
+            arena_backing: arena::ArenaBacking::Heap,
+        }
+    }
+}
+
+impl RuntimeConfig {
+    /// Arena configuration derived from the runtime settings
+    pub fn arena_config(&self) -> arena::ArenaConfig {
+        arena::ArenaConfig {
+            capacity: self.arena_size,
+            growth: self.arena_growth,
+            max_capacity: self.arena_max_size,
+            backing: self.arena_backing,
+            ..arena::ArenaConfig::default()
         }
     }
 }
 
 /// Initialize the Super-C Runtime
 pub fn init(config: RuntimeConfig) -> Result<(), &'static str> {
-    // TODO: Initialize arenas, scheduler, FFI bindings
+    ffi::init_native().map_err(|_| "native runtime failed to initialize")?;
+    if config.cuda_enabled {
+        ffi::init_gpu(ffi::GpuPreference::Performance)
+            .map_err(|_| "GPU backend failed to initialize")?;
+    }
     Ok(())
 }
@@ -0,0 +1,308 @@
+//! Automatic batching of small native tasks
+//!
+//! Batchable tasks wait in a group keyed by kernel and target. A group is
+//! dispatched as one native call once it reaches the task or byte limit,
+//! or when its oldest task has waited `max_latency`, whichever comes first.
+
+use std::collections::HashMap;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use super::dispatch::{execute_batch, DispatchPolicy, Workload};
+use super::pool::Spawner;
+use super::profile::DispatchProfile;
+use super::task::{Task, TaskPriority, TaskResult, TaskState};
+use super::watchdog::Watchdog;
+use crate::ffi::DispatchTarget;
+
+/// Limits for merging tasks into one dispatch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchConfig {
+    /// Longest a task waits for company before its group is dispatched
+    pub max_latency: Duration,
+    /// Tasks per dispatch
+    pub max_tasks: usize,
+    /// Input bytes per dispatch; larger tasks are never batched
+    pub max_bytes: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_latency: Duration::from_micros(200),
+            max_tasks: 64,
+            max_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// Batching counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchStats {
+    /// Combined dispatches issued
+    pub batches: u64,
+    /// Tasks that went through them
+    pub tasks: u64,
+}
+
+type BatchKey = (u32, DispatchTarget);
+
+struct Group {
+    generation: u64,
+    priority: TaskPriority,
+    bytes: usize,
+    members: Vec<(Task, Arc<TaskState>)>,
+}
+
+pub(crate) struct Batcher {
+    config: BatchConfig,
+    groups: Mutex<HashMap<BatchKey, Group>>,
+    next_generation: AtomicU64,
+    stats: Mutex<BatchStats>,
+    spawner: Spawner,
+    profile: Arc<DispatchProfile>,
+    /// Custom dispatch policy, told about batch timings like single runs
+    policy: Option<Arc<dyn DispatchPolicy>>,
+}
+
+impl Batcher {
+    pub fn new(
+        config: BatchConfig,
+        spawner: Spawner,
+        profile: Arc<DispatchProfile>,
+        policy: Option<Arc<dyn DispatchPolicy>>,
+    ) -> Self {
+        Self {
+            config,
+            groups: Mutex::new(HashMap::new()),
+            next_generation: AtomicU64::new(0),
+            stats: Mutex::new(BatchStats::default()),
+            spawner,
+            profile,
+            policy,
+        }
+    }
+
+    /// Whether `task` may be merged with others
+    pub fn accepts<T>(&self, task: &Task<T>) -> bool {
+        task.batchable
+            && task.is_native()
+            && !task.data.is_null()
+            && task.data_size <= self.config.max_bytes
+            && (task.output.is_null() || task.output_size == task.data_size)
+    }
+
+    pub fn stats(&self) -> BatchStats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Queue `task`; the first task of a group arms its latency timer
+    pub fn add(
+        self: &Arc<Self>,
+        task: Task,
+        state: Arc<TaskState>,
+        target: DispatchTarget,
+        watchdog: &Watchdog,
+    ) {
+        let key = (task.kernel_id, target);
+        let mut groups = self.groups.lock().unwrap();
+        if let Some(group) = groups.get(&key) {
+            if group.bytes + task.data_size > self.config.max_bytes {
+                let full = groups.remove(&key).unwrap();
+                self.dispatch(key, full);
+            }
+        }
+        let group = groups.entry(key).or_insert_with(|| {
+            let generation = self.next_generation.fetch_add(1, Ordering::Relaxed);
+            let batcher: Weak<Self> = Arc::downgrade(self);
+            watchdog.schedule(Instant::now() + self.config.max_latency, move || {
+                if let Some(batcher) = batcher.upgrade() {
+                    batcher.flush(key, generation);
+                }
+            });
+            Group {
+                generation,
+                priority: task.priority,
+                bytes: 0,
+                members: Vec::new(),
+            }
+        });
+        group.priority = group.priority.max(task.priority);
+        group.bytes += task.data_size;
+        group.members.push((task, state));
+        if group.members.len() >= self.config.max_tasks {
+            let full = groups.remove(&key).unwrap();
+            self.dispatch(key, full);
+        }
+    }
+
+    /// Latency timer: dispatch the group if it is still the one that armed it
+    fn flush(&self, key: BatchKey, generation: u64) {
+        let mut groups = self.groups.lock().unwrap();
+        if groups.get(&key).map(|group| group.generation) == Some(generation) {
+            let group = groups.remove(&key).unwrap();
+            self.dispatch(key, group);
+        }
+    }
+
+    /// Dispatch every waiting group now
+    pub fn flush_all(&self) {
+        let groups = mem::take(&mut *self.groups.lock().unwrap());
+        for (key, group) in groups {
+            self.dispatch(key, group);
+        }
+    }
+
+    fn dispatch(&self, (kind, target): BatchKey, group: Group) {
+        let profile = Arc::clone(&self.profile);
+        let policy = self.policy.clone();
+        let mut stats = self.stats.lock().unwrap();
+        stats.batches += 1;
+        stats.tasks += group.members.len() as u64;
+        drop(stats);
+        let job = move || {
+            // Cancelled and timed-out members are left out of the dispatch
+            let (tasks, states): (Vec<_>, Vec<_>) = group
+                .members
+                .into_iter()
+                .filter(|(_, state)| state.claim())
+                .unzip();
+            if tasks.is_empty() {
+                return;
+            }
+            let start = Instant::now();
+            // A panicking custom policy fails the batch, not the worker
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                let result = execute_batch(&tasks, target);
+                // Batched outputs are the same size as their inputs
+                let bytes = tasks.iter().map(|task| task.data_size).sum();
+                let workload = Workload {
+                    bytes,
+                    output_bytes: bytes,
+                    ..Workload::of(&tasks[0])
+                };
+                match result {
+                    TaskResult::Success => {
+                        let elapsed = start.elapsed();
+                        profile.record(kind, bytes, target, elapsed);
+                        if let Some(policy) = policy {
+                            policy.observe(&workload, target, elapsed);
+                        }
+                    }
+                    _ => profile.record_failure(kind, bytes, target),
+                }
+                result
+            }))
+            .unwrap_or(TaskResult::Failed);
+            for (mut task, state) in tasks.into_iter().zip(states) {
+                if state.expired() {
+                    state.finish(TaskResult::TimedOut, None);
+                    continue;
+                }
+                let output = (result == TaskResult::Success).then(|| task.native_output());
+                state.finish(result, output);
+            }
+        };
+        self.spawner.spawn(Box::new(job), group.priority);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::{Scheduler, SchedulerConfig, TaskTarget};
+    use std::ffi::c_void;
+    use std::sync::atomic::AtomicUsize;
+
+    /// Counts the bytes it is told about
+    #[derive(Default)]
+    struct Observed(AtomicUsize);
+
+    impl DispatchPolicy for Observed {
+        fn choose(&self, _: &Workload, _: &[DispatchTarget]) -> DispatchTarget {
+            DispatchTarget::Cpu
+        }
+
+        fn observe(&self, workload: &Workload, _: DispatchTarget, _: Duration) {
+            self.0.fetch_add(workload.bytes, Ordering::Relaxed);
+        }
+    }
+
+    fn copy_task(id: u64, input: &[u32], output: &mut [u32]) -> Task {
+        let mut task = unsafe {
+            Task::new(id, TaskTarget::Cpu).with_buffers(
+                input.as_ptr() as *const c_void,
+                std::mem::size_of_val(input),
+                output.as_mut_ptr() as *mut c_void,
+                std::mem::size_of_val(output),
+            )
+        };
+        task.batchable = true;
+        task
+    }
+
+    #[test]
+    fn test_batches_split_results() {
+        let policy = Arc::new(Observed::default());
+        let scheduler = Scheduler::new(SchedulerConfig {
+            worker_threads: 2,
+            dispatch_policy: Some(policy.clone()),
+            batching: Some(BatchConfig {
+                max_latency: Duration::from_millis(20),
+                max_tasks: 4,
+                ..BatchConfig::default()
+            }),
+            ..SchedulerConfig::default()
+        });
+        let inputs: Vec<Vec<u32>> = (0..6).map(|i| vec![i; 4 + i as usize]).collect();
+        let mut outputs: Vec<Vec<u32>> = inputs.iter().map(|input| vec![0; input.len()]).collect();
+
+        // Four tasks fill a batch and go at once; the last two wait out the latency
+        let start = Instant::now();
+        let handles: Vec<_> = inputs
+            .iter()
+            .zip(outputs.iter_mut())
+            .enumerate()
+            .map(|(id, (input, output))| scheduler.submit(copy_task(id as u64, input, output)))
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.wait(), Ok(()));
+        }
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(outputs, inputs);
+        assert_eq!(
+            scheduler.batch_stats(),
+            BatchStats {
+                batches: 2,
+                tasks: 6
+            }
+        );
+        // The custom policy hears about batched runs too
+        let batched: usize = inputs.iter().map(|input| input.len() * 4).sum();
+        assert_eq!(policy.0.load(Ordering::Relaxed), batched);
+
+        // Owned buffers get their slice of the combined output back
+        let owned: Vec<_> = (0..3u8)
+            .map(|i| {
+                let mut task = Task::native(20 + i as u64, TaskTarget::Cpu, vec![i; 16], 16);
+                task.batchable = true;
+                scheduler.submit(task)
+            })
+            .collect();
+        for (i, handle) in owned.into_iter().enumerate() {
+            assert_eq!(handle.wait(), Ok(vec![i as u8; 16]));
+        }
+        assert_eq!(scheduler.batch_stats().tasks, 9);
+
+        // Tasks too large to batch take the normal path
+        let large = vec![7u32; 1 << 19];
+        let mut copy = vec![0u32; large.len()];
+        let handle = scheduler.submit(copy_task(9, &large, &mut copy));
+        assert_eq!(handle.wait(), Ok(()));
+        assert_eq!(copy, large);
+        assert_eq!(scheduler.batch_stats().tasks, 9);
+    }
+}
@@ -0,0 +1,209 @@
+//! Transfer-aware cost model
+//!
+//! Estimates a workload's wall time on each target as launch overhead,
+//! plus compute time at the target's throughput, plus whatever host/device
+//! copies its data residency implies, and dispatches to the cheapest.
+//! Parameters start from conservative defaults and are refined from
+//! measured runs and transfers; attach the model to a `StagingPool` as its
+//! `TransferObserver` to learn the bus bandwidth.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::dispatch::{DispatchPolicy, Workload};
+use super::profile::SMOOTHING;
+use super::task::Residency;
+use crate::arena::TransferObserver;
+use crate::ffi::DispatchTarget;
+
+/// Fixed and per-byte cost of running on one target
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TargetCost {
+    pub launch_ns: f64,
+    /// Compute throughput in bytes per nanosecond (GB/s)
+    pub bytes_per_ns: f64,
+}
+
+/// Cost model parameters; bandwidths in bytes per nanosecond (GB/s)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CostParams {
+    pub cpu: TargetCost,
+    pub cpu_asm: TargetCost,
+    pub gpu: TargetCost,
+    /// Host to device copy bandwidth
+    pub upload_bytes_per_ns: f64,
+    /// Device to host copy bandwidth
+    pub download_bytes_per_ns: f64,
+}
+
+impl Default for CostParams {
+    fn default() -> Self {
+        Self {
+            cpu: TargetCost {
+                launch_ns: 0.0,
+                bytes_per_ns: 2.0,
+            },
+            cpu_asm: TargetCost {
+                launch_ns: 200.0,
+                bytes_per_ns: 8.0,
+            },
+            gpu: TargetCost {
+                launch_ns: 10_000.0,
+                bytes_per_ns: 200.0,
+            },
+            upload_bytes_per_ns: 12.0,
+            download_bytes_per_ns: 12.0,
+        }
+    }
+}
+
+impl CostParams {
+    fn target(&self, target: DispatchTarget) -> &TargetCost {
+        match target {
+            DispatchTarget::Cpu => &self.cpu,
+            DispatchTarget::CpuAsm => &self.cpu_asm,
+            DispatchTarget::Gpu => &self.gpu,
+        }
+    }
+
+    /// Copy time implied by running `workload` on `target`
+    fn transfer_ns(&self, workload: &Workload, target: DispatchTarget) -> f64 {
+        match (workload.residency, target) {
+            (Residency::Host, DispatchTarget::Gpu) => {
+                workload.bytes as f64 / self.upload_bytes_per_ns
+                    + workload.output_bytes as f64 / self.download_bytes_per_ns
+            }
+            (Residency::Device, DispatchTarget::Cpu | DispatchTarget::CpuAsm) => {
+                workload.bytes as f64 / self.download_bytes_per_ns
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+/// Dispatch policy choosing the target with the lowest estimated time
+#[derive(Debug, Default)]
+pub struct CostModel {
+    params: Mutex<CostParams>,
+}
+
+fn smooth(current: &mut f64, sample: f64) {
+    if sample.is_finite() && sample > 0.0 {
+        *current += SMOOTHING * (sample - *current);
+    }
+}
+
+impl CostModel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_params(params: CostParams) -> Self {
+        Self {
+            params: Mutex::new(params),
+        }
+    }
+
+    pub fn params(&self) -> CostParams {
+        *self.params.lock().unwrap()
+    }
+
+    /// Estimated wall time of `workload` on `target`, copies included
+    pub fn estimate(&self, workload: &Workload, target: DispatchTarget) -> Duration {
+        let params = self.params.lock().unwrap();
+        let cost = params.target(target);
+        let ns = cost.launch_ns
+            + workload.bytes as f64 / cost.bytes_per_ns
+            + params.transfer_ns(workload, target);
+        Duration::from_nanos(ns as u64)
+    }
+
+    /// Fold in a measured host to device copy
+    pub fn record_upload(&self, bytes: usize, elapsed: Duration) {
+        let mut params = self.params.lock().unwrap();
+        smooth(
+            &mut params.upload_bytes_per_ns,
+            bytes as f64 / elapsed.as_nanos() as f64,
+        );
+    }
+
+    /// Fold in a measured device to host copy
+    pub fn record_download(&self, bytes: usize, elapsed: Duration) {
+        let mut params = self.params.lock().unwrap();
+        smooth(
+            &mut params.download_bytes_per_ns,
+            bytes as f64 / elapsed.as_nanos() as f64,
+        );
+    }
+}
+
+impl DispatchPolicy for CostModel {
+    fn choose(&self, workload: &Workload, candidates: &[DispatchTarget]) -> DispatchTarget {
+        candidates
+            .iter()
+            .copied()
+            .min_by_key(|&target| self.estimate(workload, target))
+            .unwrap()
+    }
+
+    /// Refine the target's throughput from the run time left after the
+    /// modelled launch and copies
+    fn observe(&self, workload: &Workload, target: DispatchTarget, elapsed: Duration) {
+        let mut params = self.params.lock().unwrap();
+        let overhead = params.target(target).launch_ns + params.transfer_ns(workload, target);
+        let compute_ns = elapsed.as_nanos() as f64 - overhead;
+        let throughput = workload.bytes as f64 / compute_ns;
+        let cost = match target {
+            DispatchTarget::Cpu => &mut params.cpu,
+            DispatchTarget::CpuAsm => &mut params.cpu_asm,
+            DispatchTarget::Gpu => &mut params.gpu,
+        };
+        smooth(&mut cost.bytes_per_ns, throughput);
+    }
+}
+
+impl TransferObserver for CostModel {
+    fn uploaded(&self, bytes: usize, elapsed: Duration) {
+        self.record_upload(bytes, elapsed);
+    }
+
+    fn downloaded(&self, bytes: usize, elapsed: Duration) {
+        self.record_download(bytes, elapsed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::TaskTarget;
+
+    #[test]
+    fn test_residency_decides_placement() {
+        let model = CostModel::new();
+        let mut workload = Workload {
+            kind: 0,
+            target: TaskTarget::Auto,
+            bytes: 8 << 20,
+            output_bytes: 8 << 20,
+            residency: Residency::Host,
+        };
+        // Copying 16 MB over the bus costs more than the ASM path saves
+        assert_eq!(model.select(&workload, true, true), DispatchTarget::CpuAsm);
+        workload.residency = Residency::Device;
+        assert_eq!(model.select(&workload, true, true), DispatchTarget::Gpu);
+        assert_eq!(model.select(&workload, false, true), DispatchTarget::CpuAsm);
+
+        // A faster measured bus flips host-resident data to the GPU
+        workload.residency = Residency::Host;
+        for _ in 0..20 {
+            model.record_upload(64 << 20, Duration::from_micros(1000));
+            model.record_download(64 << 20, Duration::from_micros(1000));
+        }
+        assert_eq!(model.select(&workload, true, true), DispatchTarget::Gpu);
+
+        // Measured runs pull throughput towards what was observed
+        let before = model.params().cpu.bytes_per_ns;
+        model.observe(&workload, DispatchTarget::Cpu, Duration::from_micros(800));
+        assert!(model.params().cpu.bytes_per_ns > before);
+    }
+}
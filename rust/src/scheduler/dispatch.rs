@@ -1,35 +1,190 @@
 //! Dispatch logic for CPU/GPU execution
 
-use super::task::{Task, TaskTarget};
-use crate::ffi::DispatchTarget;
+use std::ffi::c_void;
+use std::time::Duration;
 
-/// Determine the best execution target for a task
-pub fn select_target(task: &Task, gpu_available: bool, asm_enabled: bool) -> DispatchTarget {
-    match task.target {
-        TaskTarget::Cpu => DispatchTarget::Cpu,
-        TaskTarget::CpuAsm => {
-            if asm_enabled {
-                DispatchTarget::CpuAsm
+use super::task::{Residency, Task, TaskResult, TaskTarget};
+use crate::ffi::{self, DispatchTarget};
+
+/// What a dispatch policy knows about a task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Workload {
+    /// Task kind (the kernel id)
+    pub kind: u32,
+    pub target: TaskTarget,
+    /// Input bytes
+    pub bytes: usize,
+    /// Output bytes
+    pub output_bytes: usize,
+    /// Where the input currently lives
+    pub residency: Residency,
+}
+
+impl Workload {
+    pub fn of<T>(task: &Task<T>) -> Self {
+        Self {
+            kind: task.kernel_id,
+            target: task.target,
+            bytes: task.data_size,
+            output_bytes: if task.output.is_null() {
+                task.data_size
             } else {
-                DispatchTarget::Cpu
-            }
+                task.output_size
+            },
+            residency: task.residency,
         }
-        TaskTarget::Gpu => {
-            if gpu_available {
-                DispatchTarget::Gpu
-            } else {
-                DispatchTarget::Cpu
+    }
+}
+
+/// Strategy for placing `TaskTarget::Auto` work
+pub trait DispatchPolicy: Send + Sync {
+    /// Pick one of `candidates` (always `Cpu` plus at least one other)
+    fn choose(&self, workload: &Workload, candidates: &[DispatchTarget]) -> DispatchTarget;
+
+    /// Feedback after `workload` ran successfully on `target`
+    fn observe(&self, _workload: &Workload, _target: DispatchTarget, _elapsed: Duration) {}
+
+    /// Final target; explicit task targets win, falling back to the CPU
+    /// when the requested unit is unavailable
+    fn select(
+        &self,
+        workload: &Workload,
+        gpu_available: bool,
+        asm_enabled: bool,
+    ) -> DispatchTarget {
+        let asm = if asm_enabled {
+            DispatchTarget::CpuAsm
+        } else {
+            DispatchTarget::Cpu
+        };
+        match workload.target {
+            TaskTarget::Cpu => DispatchTarget::Cpu,
+            TaskTarget::CpuAsm => asm,
+            TaskTarget::Gpu if gpu_available => DispatchTarget::Gpu,
+            TaskTarget::Gpu => DispatchTarget::Cpu,
+            TaskTarget::Auto => {
+                let candidates = [
+                    Some(DispatchTarget::Cpu),
+                    asm_enabled.then_some(DispatchTarget::CpuAsm),
+                    gpu_available.then_some(DispatchTarget::Gpu),
+                ];
+                let candidates: Vec<_> = candidates.into_iter().flatten().collect();
+                if candidates.len() == 1 {
+                    DispatchTarget::Cpu
+                } else {
+                    self.choose(workload, &candidates)
+                }
             }
         }
-        TaskTarget::Auto => {
-            // Heuristic: prefer GPU for large workloads
-            if gpu_available && task.data_size > 1024 * 1024 {
-                DispatchTarget::Gpu
-            } else if asm_enabled {
-                DispatchTarget::CpuAsm
-            } else {
-                DispatchTarget::Cpu
+    }
+}
+
+/// Fixed size threshold: GPU above 1 MB, otherwise the ASM paths
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeuristicPolicy;
+
+impl DispatchPolicy for HeuristicPolicy {
+    fn choose(&self, workload: &Workload, candidates: &[DispatchTarget]) -> DispatchTarget {
+        // Heuristic: prefer GPU for large workloads
+        if candidates.contains(&DispatchTarget::Gpu) && workload.bytes > 1024 * 1024 {
+            DispatchTarget::Gpu
+        } else if candidates.contains(&DispatchTarget::CpuAsm) {
+            DispatchTarget::CpuAsm
+        } else {
+            DispatchTarget::Cpu
+        }
+    }
+}
+
+/// Determine the best execution target for a task
+pub fn select_target<T>(task: &Task<T>, gpu_available: bool, asm_enabled: bool) -> DispatchTarget {
+    HeuristicPolicy.select(&Workload::of(task), gpu_available, asm_enabled)
+}
+
+/// Run a raw task on `target` through the native layer
+///
+/// An owned output buffer is cut down to the size the native call reports.
+pub(crate) fn execute<T>(task: &mut Task<T>, target: DispatchTarget) -> TaskResult {
+    let mut scratch = Vec::new();
+    let (output, mut output_size) = if task.output.is_null() {
+        scratch.resize(task.data_size, 0u8);
+        (scratch.as_mut_ptr() as *mut c_void, task.data_size)
+    } else {
+        (task.output, task.output_size)
+    };
+    let code = unsafe {
+        launch(
+            target,
+            task.kernel_id,
+            task.data,
+            task.data_size,
+            output,
+            &mut output_size,
+        )
+    };
+    if code != 0 {
+        return TaskResult::Failed;
+    }
+    if let Some(buffers) = &mut task.buffers {
+        buffers.output.truncate(output_size);
+    }
+    TaskResult::Success
+}
+
+/// Run same-kernel tasks as one dispatch over their concatenated inputs,
+/// then copy each task's slice of the output back to it
+///
+/// Only valid for elementwise kernels whose output is the size of the input.
+pub(crate) fn execute_batch(tasks: &[Task], target: DispatchTarget) -> TaskResult {
+    let total: usize = tasks.iter().map(|task| task.data_size).sum();
+    let mut input = Vec::with_capacity(total);
+    for task in tasks {
+        input.extend_from_slice(unsafe {
+            std::slice::from_raw_parts(task.data as *const u8, task.data_size)
+        });
+    }
+    let mut output = vec![0u8; total];
+    let mut output_size = total;
+    let code = unsafe {
+        launch(
+            target,
+            tasks[0].kernel_id,
+            input.as_ptr() as *const c_void,
+            total,
+            output.as_mut_ptr() as *mut c_void,
+            &mut output_size,
+        )
+    };
+    if code != 0 || output_size != total {
+        return TaskResult::Failed;
+    }
+    let mut offset = 0;
+    for task in tasks {
+        if !task.output.is_null() {
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    output[offset..].as_ptr(),
+                    task.output as *mut u8,
+                    task.data_size,
+                );
             }
         }
+        offset += task.data_size;
+    }
+    TaskResult::Success
+}
+
+unsafe fn launch(
+    target: DispatchTarget,
+    kernel_id: u32,
+    data: *const c_void,
+    size: usize,
+    output: *mut c_void,
+    output_size: &mut usize,
+) -> i32 {
+    match target {
+        DispatchTarget::Cpu => ffi::native_execute_cpu(data, size, output, output_size),
+        DispatchTarget::CpuAsm => ffi::native_execute_cpu_asm(data, size, output, output_size),
+        DispatchTarget::Gpu => ffi::gpu_launch_kernel(kernel_id, data, size, output, output_size),
     }
 }
@@ -0,0 +1,62 @@
+//! Async integration
+//!
+//! `TaskHandle` is a `Future` resolving to the task's output. Only std's
+//! `Waker` is used, so handles can be awaited from any executor; waiting
+//! never blocks the executor thread.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use super::task::{TaskHandle, TaskResult};
+
+impl<T: 'static> Future for TaskHandle<T> {
+    type Output = Result<T, TaskResult>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.state.poll_result(cx.waker()) {
+            TaskResult::Pending => Poll::Pending,
+            result => Poll::Ready(self.output(result)),
+        }
+    }
+}
+
+/// Future returned by `join_all`
+pub struct JoinAll<T> {
+    handles: Vec<Option<TaskHandle<T>>>,
+    /// Boxed so the future is `Unpin` whatever `T` is
+    results: Box<[Option<Result<T, TaskResult>>]>,
+}
+
+/// Await every handle, yielding results in the order given
+pub fn join_all<T, I>(handles: I) -> JoinAll<T>
+where
+    I: IntoIterator<Item = TaskHandle<T>>,
+{
+    let handles: Vec<_> = handles.into_iter().map(Some).collect();
+    let results = handles.iter().map(|_| None).collect();
+    JoinAll { handles, results }
+}
+
+impl<T: 'static> Future for JoinAll<T> {
+    type Output = Vec<Result<T, TaskResult>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut pending = false;
+        for (slot, result) in this.handles.iter_mut().zip(&mut this.results) {
+            let Some(handle) = slot else { continue };
+            match Pin::new(handle).poll(cx) {
+                Poll::Ready(output) => {
+                    *result = Some(output);
+                    *slot = None;
+                }
+                Poll::Pending => pending = true,
+            }
+        }
+        if pending {
+            return Poll::Pending;
+        }
+        Poll::Ready(this.results.iter_mut().map(|r| r.take().unwrap()).collect())
+    }
+}
@@ -0,0 +1,200 @@
+//! Task dependency graphs
+//!
+//! A gated task waits on its predecessors through completion callbacks:
+//! it is queued once the last predecessor succeeds, and cancelled (along
+//! with everything downstream of it) as soon as one fails.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::pool::{Job, Spawner};
+use super::task::{Task, TaskHandle, TaskPriority, TaskResult, TaskState};
+
+struct Gate {
+    remaining: AtomicUsize,
+    job: Mutex<Option<Job>>,
+    priority: TaskPriority,
+    state: Arc<TaskState>,
+    spawner: Spawner,
+}
+
+/// Queue `job` once every task in `deps` has succeeded
+pub(crate) fn spawn_after(
+    deps: Vec<Arc<TaskState>>,
+    job: Job,
+    priority: TaskPriority,
+    state: Arc<TaskState>,
+    spawner: Spawner,
+) {
+    let gate = Arc::new(Gate {
+        remaining: AtomicUsize::new(deps.len()),
+        job: Mutex::new(Some(job)),
+        priority,
+        state,
+        spawner,
+    });
+    for dep in deps {
+        let gate = Arc::clone(&gate);
+        dep.on_complete(Box::new(move |result| {
+            if result == TaskResult::Success {
+                if gate.remaining.fetch_sub(1, Ordering::AcqRel) > 1 {
+                    return;
+                }
+                if let Some(job) = gate.job.lock().unwrap().take() {
+                    gate.spawner.spawn(job, gate.priority);
+                }
+            } else if let Some(job) = gate.job.lock().unwrap().take() {
+                drop(job);
+                // Finishing runs this task's own callbacks, cascading downstream
+                gate.state.finish(TaskResult::Cancelled, None);
+            }
+        }));
+    }
+}
+
+/// Node in a `TaskGraph`, typed by the task's output
+pub struct NodeId<T = ()> {
+    index: usize,
+    _output: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for NodeId<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for NodeId<T> {}
+
+impl<T> fmt::Debug for NodeId<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NodeId({})", self.index)
+    }
+}
+
+/// Graph validation failure
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphError {
+    /// Ids of the tasks that sit on or behind a dependency cycle
+    Cycle(Vec<u64>),
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::Cycle(ids) => write!(f, "dependency cycle among tasks {ids:?}"),
+        }
+    }
+}
+
+struct Node {
+    task: Task,
+    deps: Vec<usize>,
+}
+
+/// DAG of tasks, validated and submitted as a whole
+#[derive(Default)]
+pub struct TaskGraph {
+    nodes: Vec<Node>,
+}
+
+impl TaskGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add<T: Send + 'static>(&mut self, task: Task<T>) -> NodeId<T> {
+        self.nodes.push(Node {
+            task: task.cast(),
+            deps: Vec::new(),
+        });
+        NodeId {
+            index: self.nodes.len() - 1,
+            _output: PhantomData,
+        }
+    }
+
+    /// Run `node` only after `on` has succeeded
+    pub fn depend<A, B>(&mut self, node: NodeId<A>, on: NodeId<B>) {
+        assert!(
+            node.index < self.nodes.len() && on.index < self.nodes.len(),
+            "node belongs to another graph"
+        );
+        self.nodes[node.index].deps.push(on.index);
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Check that the graph is acyclic
+    pub fn validate(&self) -> Result<(), GraphError> {
+        self.order().map(drop)
+    }
+
+    /// Topological order (Kahn's algorithm)
+    fn order(&self) -> Result<Vec<usize>, GraphError> {
+        let mut indegree: Vec<usize> = self.nodes.iter().map(|node| node.deps.len()).collect();
+        let mut dependents = vec![Vec::new(); self.nodes.len()];
+        for (index, node) in self.nodes.iter().enumerate() {
+            for &dep in &node.deps {
+                dependents[dep].push(index);
+            }
+        }
+        let mut ready: VecDeque<usize> = (0..self.nodes.len())
+            .filter(|&index| indegree[index] == 0)
+            .collect();
+        let mut order = Vec::with_capacity(self.nodes.len());
+        while let Some(index) = ready.pop_front() {
+            order.push(index);
+            for &next in &dependents[index] {
+                indegree[next] -= 1;
+                if indegree[next] == 0 {
+                    ready.push_back(next);
+                }
+            }
+        }
+        if order.len() == self.nodes.len() {
+            Ok(order)
+        } else {
+            let stuck = (0..self.nodes.len())
+                .filter(|&index| indegree[index] > 0)
+                .map(|index| self.nodes[index].task.id)
+                .collect();
+            Err(GraphError::Cycle(stuck))
+        }
+    }
+
+    /// Tasks in submission order, each with its predecessors' node indices
+    pub(crate) fn into_ordered(self) -> Result<Vec<(usize, Task, Vec<usize>)>, GraphError> {
+        let order = self.order()?;
+        let mut nodes: Vec<Option<Node>> = self.nodes.into_iter().map(Some).collect();
+        Ok(order
+            .into_iter()
+            .map(|index| {
+                let node = nodes[index].take().unwrap();
+                (index, node.task, node.deps)
+            })
+            .collect())
+    }
+}
+
+/// Handles for the tasks of a submitted graph
+pub struct GraphHandles {
+    pub(crate) handles: Vec<Option<TaskHandle>>,
+}
+
+impl GraphHandles {
+    /// Take the typed handle for `node`; `None` if already taken
+    pub fn take<T>(&mut self, node: NodeId<T>) -> Option<TaskHandle<T>> {
+        let handle = self.handles.get_mut(node.index)?.take()?;
+        Some(TaskHandle::new(handle.id, handle.state))
+    }
+}
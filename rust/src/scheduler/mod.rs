@@ -5,26 +5,65 @@
 
 mod task;
 mod dispatch;
+mod pool;
+mod graph;
+mod future;
+mod watchdog;
+mod profile;
+mod cost;
+mod batch;
 
 pub use task::*;
 pub use dispatch::*;
+pub use graph::*;
+pub use future::*;
+pub use profile::*;
+pub use cost::*;
+pub use batch::*;
+
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::ffi;
+use batch::Batcher;
+use pool::{Job, ThreadPool};
+use watchdog::Watchdog;
 
 /// Scheduler configuration
 pub struct SchedulerConfig {
     /// Maximum concurrent tasks
     pub max_tasks: usize,
+    /// Worker threads, capped at `max_tasks`
+    pub worker_threads: usize,
     /// Prefer GPU when available
     pub prefer_gpu: bool,
     /// Enable ASM hot paths
     pub enable_asm: bool,
+    /// Queue time after which a `Low` task runs ahead of `High` and `Normal`
+    pub low_priority_aging: Duration,
+    /// Dispatch profile loaded at startup and saved on drop
+    pub profile_path: Option<PathBuf>,
+    /// Placement of `Auto` tasks; the measured profile when unset
+    pub dispatch_policy: Option<Arc<dyn DispatchPolicy>>,
+    /// Merge small `batchable` tasks into combined dispatches; off when unset
+    pub batching: Option<BatchConfig>,
 }
 
 impl Default for SchedulerConfig {
     fn default() -> Self {
         Self {
             max_tasks: 64,
+            worker_threads: std::thread::available_parallelism().map_or(4, |n| n.get()),
             prefer_gpu: true,
             enable_asm: true,
+            low_priority_aging: Duration::from_millis(100),
+            profile_path: None,
+            dispatch_policy: None,
+            batching: None,
         }
     }
 }
@@ -32,23 +71,593 @@ impl Default for SchedulerConfig {
 /// Main scheduler instance
 pub struct Scheduler {
     config: SchedulerConfig,
-    // TODO: task queue, thread pool, etc.
+    pool: ThreadPool,
+    watchdog: Watchdog,
+    profile: Arc<DispatchProfile>,
+    batcher: Option<Arc<Batcher>>,
+    /// Ids for tasks created by `spawn`
+    next_id: AtomicU64,
 }
 
 impl Scheduler {
+    /// Create a scheduler, starting the native runtime if needed
+    ///
+    /// A profile at `profile_path` that cannot be read is ignored and
+    /// learning starts afresh; `try_new` reports it instead.
+    ///
+    /// # Panics
+    /// If the native runtime fails to initialize.
     pub fn new(config: SchedulerConfig) -> Self {
-        Self { config }
+        ffi::init_native().expect("native runtime failed to initialize");
+        let profile = config
+            .profile_path
+            .as_ref()
+            .and_then(|path| DispatchProfile::load(path).ok())
+            .unwrap_or_default();
+        Self::with_profile(config, profile)
+    }
+
+    /// Like `new`, but fails if the native runtime does not start or the
+    /// profile exists and cannot be read
+    pub fn try_new(config: SchedulerConfig) -> io::Result<Self> {
+        ffi::init_native().map_err(|code| {
+            io::Error::other(format!("native runtime failed to initialize ({code})"))
+        })?;
+        let profile = match &config.profile_path {
+            Some(path) => match DispatchProfile::load(path) {
+                Err(err) if err.kind() == io::ErrorKind::NotFound => DispatchProfile::default(),
+                loaded => loaded?,
+            },
+            None => DispatchProfile::default(),
+        };
+        Ok(Self::with_profile(config, profile))
+    }
+
+    fn with_profile(config: SchedulerConfig, profile: DispatchProfile) -> Self {
+        let pool = ThreadPool::new(
+            config.worker_threads.min(config.max_tasks),
+            config.low_priority_aging,
+        );
+        let profile = Arc::new(profile);
+        let batcher = config.batching.map(|batching| {
+            Arc::new(Batcher::new(
+                batching,
+                pool.spawner(),
+                Arc::clone(&profile),
+                config.dispatch_policy.clone(),
+            ))
+        });
+        Self {
+            config,
+            pool,
+            watchdog: Watchdog::new(),
+            profile,
+            batcher,
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Number of worker threads
+    pub fn workers(&self) -> usize {
+        self.pool.threads()
     }
 
     /// Submit a task for execution
-    pub fn submit(&mut self, task: Task) -> TaskHandle {
-        // TODO: actual scheduling logic
-        TaskHandle { id: 0 }
+    ///
+    /// Tasks with dependencies (`Task::after`) are held back until every
+    /// predecessor has succeeded, and cancelled if any of them does not.
+    /// A task still unfinished at its deadline is reported as `TimedOut`;
+    /// a running task over borrowed buffers (`Task::with_buffers`) only once
+    /// its native call has returned.
+    /// With batching enabled, `batchable` native tasks may wait up to the
+    /// batch latency to be merged with others of the same kernel.
+    pub fn submit<T: Send + 'static>(&self, mut task: Task<T>) -> TaskHandle<T> {
+        let state = Arc::new(if task.borrows_buffers() {
+            TaskState::borrowed()
+        } else {
+            TaskState::new()
+        });
+        let handle = TaskHandle::new(task.id, Arc::clone(&state));
+        let deps = std::mem::take(&mut task.deps);
+        let task_priority = task.priority;
+        let task_deadline = task.deadline;
+        let done = Arc::clone(&state);
+        if let Some(deadline) = task_deadline {
+            self.watchdog.watch(&state, deadline);
+        }
+        let cooperative = task.cooperative;
+        let job: Job = match task.body.take() {
+            Some(body) => Box::new(move || {
+                if !done.claim() {
+                    return;
+                }
+                let token = done.token.clone();
+                match panic::catch_unwind(AssertUnwindSafe(|| body(&token))) {
+                    // Only a cancellable body stops early on the token
+                    Ok(_) if cooperative && token.is_cancelled() => {
+                        done.finish(TaskResult::Cancelled, None)
+                    }
+                    Ok(output) => done.finish(TaskResult::Success, Some(output)),
+                    Err(_) => done.finish(TaskResult::Failed, None),
+                };
+            }),
+            None => {
+                let gpu_available = (self.config.prefer_gpu || task.target == TaskTarget::Gpu)
+                    && ffi::is_gpu_available();
+                let workload = Workload::of(&task);
+                let custom = self.config.dispatch_policy.clone();
+                let target = match &custom {
+                    Some(policy) => policy.select(&workload, gpu_available, self.config.enable_asm),
+                    None => self
+                        .profile
+                        .select(&workload, gpu_available, self.config.enable_asm),
+                };
+                let batcher = self
+                    .batcher
+                    .as_ref()
+                    .filter(|batcher| deps.is_empty() && batcher.accepts(&task));
+                if let Some(batcher) = batcher {
+                    batcher.add(task.cast(), state, target, &self.watchdog);
+                    return handle;
+                }
+                let profile = Arc::clone(&self.profile);
+                let mut task = task;
+                Box::new(move || {
+                    if !done.claim() {
+                        return;
+                    }
+                    let start = Instant::now();
+                    // A panicking custom policy fails the task, not the worker
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                        let result = execute(&mut task, target);
+                        if done.expired() {
+                            return TaskResult::TimedOut;
+                        }
+                        match result {
+                            // Failures count too, so a broken target is not explored forever
+                            TaskResult::Failed => {
+                                profile.record_failure(workload.kind, workload.bytes, target)
+                            }
+                            // The profile always learns; a custom policy hears about it too
+                            TaskResult::Success => {
+                                let elapsed = start.elapsed();
+                                profile.record(workload.kind, workload.bytes, target, elapsed);
+                                if let Some(policy) = custom {
+                                    policy.observe(&workload, target, elapsed);
+                                }
+                            }
+                            _ => {}
+                        }
+                        result
+                    }))
+                    .unwrap_or(TaskResult::Failed);
+                    let output = (result == TaskResult::Success).then(|| task.native_output());
+                    done.finish(result, output);
+                })
+            }
+        };
+        if deps.is_empty() {
+            self.pool.spawn(job, task_priority);
+        } else {
+            graph::spawn_after(deps, job, task_priority, state, self.pool.spawner());
+        }
+        handle
+    }
+
+    /// Validate a task graph and submit it; nothing runs if it has a cycle
+    pub fn submit_graph(&self, graph: TaskGraph) -> Result<GraphHandles, GraphError> {
+        let mut handles: Vec<Option<TaskHandle>> = (0..graph.len()).map(|_| None).collect();
+        for (index, mut task, deps) in graph.into_ordered()? {
+            for dep in deps {
+                task = task.after(handles[dep].as_ref().unwrap());
+            }
+            handles[index] = Some(self.submit(task));
+        }
+        Ok(GraphHandles { handles })
+    }
+
+    /// Submit a task now and return its handle as a future of its output
+    ///
+    /// The handle is `'static` and `Send`, so it can be handed to any
+    /// executor's spawn without borrowing the scheduler.
+    pub fn submit_async<T: Send + 'static>(&self, task: Task<T>) -> TaskHandle<T> {
+        self.submit(task)
+    }
+
+    /// Run `f` on a worker and return a handle to its output
+    pub fn spawn<T, F>(&self, f: F) -> TaskHandle<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.submit(Task::from_fn(id, f))
+    }
+
+    /// Measured execution times behind `TaskTarget::Auto`
+    pub fn profile(&self) -> &DispatchProfile {
+        &self.profile
+    }
+
+    /// Write the dispatch profile to `profile_path`, if one is configured
+    pub fn save_profile(&self) -> io::Result<()> {
+        match &self.config.profile_path {
+            Some(path) => self.profile.save(path),
+            None => Ok(()),
+        }
+    }
+
+    /// Batching counters; all zero when batching is off
+    pub fn batch_stats(&self) -> BatchStats {
+        self.batcher
+            .as_ref()
+            .map_or_else(BatchStats::default, |batcher| batcher.stats())
+    }
+
+    /// Tasks queued at `priority` and not yet started
+    pub fn queue_depth(&self, priority: TaskPriority) -> usize {
+        self.pool.depth(priority)
+    }
+
+    /// Wait for a task to complete and take its output
+    pub fn wait<T: 'static>(&self, handle: TaskHandle<T>) -> Result<T, TaskResult> {
+        handle.wait()
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        // Waiting batches still run; the pool drains them before shutting down
+        if let Some(batcher) = &self.batcher {
+            batcher.flush_all();
+        }
+        // Best effort: losing the profile only costs re-learning it
+        let _ = self.save_profile();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::c_void;
+
+    #[test]
+    fn test_pool_runs_tasks() {
+        let scheduler = Scheduler::new(SchedulerConfig {
+            worker_threads: 4,
+            ..SchedulerConfig::default()
+        });
+        assert_eq!(scheduler.workers(), 4);
+
+        let inputs: Vec<u64> = (0..64).collect();
+        let mut outputs = vec![0u64; inputs.len()];
+        let handles: Vec<_> = inputs
+            .iter()
+            .zip(outputs.iter_mut())
+            .map(|(input, output)| {
+                let task = unsafe {
+                    Task::new(*input, TaskTarget::Cpu).with_buffers(
+                        input as *const u64 as *const c_void,
+                        8,
+                        output as *mut u64 as *mut c_void,
+                        8,
+                    )
+                };
+                scheduler.submit(task)
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(scheduler.wait(handle), Ok(()));
+        }
+        assert_eq!(outputs, inputs);
+
+        // No payload: the native layer rejects it
+        let handle = scheduler.submit(Task::new(99, TaskTarget::Cpu));
+        assert_eq!(scheduler.wait(handle), Err(TaskResult::Failed));
+
+        // Owned buffers: the output comes back through the handle
+        let input: Vec<u8> = (0..32).collect();
+        let handle = scheduler.submit(Task::native(100, TaskTarget::Cpu, input.clone(), 32));
+        assert_eq!(handle.wait(), Ok(input));
+    }
+
+    #[test]
+    fn test_closure_outputs() {
+        let scheduler = Arc::new(Scheduler::new(SchedulerConfig {
+            worker_threads: 2,
+            ..SchedulerConfig::default()
+        }));
+
+        let words = scheduler.spawn(|| vec!["load", "transform", "reduce"]);
+        assert_eq!(words.wait().unwrap().len(), 3);
+
+        // Nested fan-out: waiting workers keep running queued subtasks
+        let inner = Arc::clone(&scheduler);
+        let sum = scheduler.spawn(move || {
+            let parts: Vec<_> = (0..8u64)
+                .map(|i| inner.spawn(move || (i * 100..(i + 1) * 100).sum::<u64>()))
+                .collect();
+            parts.into_iter().map(|h| h.wait().unwrap()).sum::<u64>()
+        });
+        assert_eq!(scheduler.wait(sum), Ok((0..800).sum()));
+
+        let panicked = scheduler.submit(Task::<u32>::from_fn(7, || panic!("task failed")));
+        assert_eq!(panicked.id, 7);
+        assert_eq!(panicked.wait(), Err(TaskResult::Failed));
+    }
+
+    #[test]
+    fn test_panicking_policy() {
+        struct Panics;
+        impl DispatchPolicy for Panics {
+            fn choose(&self, _: &Workload, _: &[ffi::DispatchTarget]) -> ffi::DispatchTarget {
+                ffi::DispatchTarget::Cpu
+            }
+
+            fn observe(&self, _: &Workload, _: ffi::DispatchTarget, _: Duration) {
+                panic!("policy failed");
+            }
+        }
+
+        let scheduler = Scheduler::new(SchedulerConfig {
+            worker_threads: 1,
+            dispatch_policy: Some(Arc::new(Panics)),
+            ..SchedulerConfig::default()
+        });
+        // The task fails and its single worker lives on
+        let task = Task::native(1, TaskTarget::Cpu, vec![1; 16], 16);
+        assert_eq!(scheduler.submit(task).wait(), Err(TaskResult::Failed));
+        assert_eq!(scheduler.spawn(|| 2).wait(), Ok(2));
+    }
+
+    /// Submit one task per priority behind a blocker on a single worker
+    fn run_by_priority(aging: Duration) -> Vec<TaskPriority> {
+        let scheduler = Scheduler::new(SchedulerConfig {
+            worker_threads: 1,
+            low_priority_aging: aging,
+            ..SchedulerConfig::default()
+        });
+        let (release, blocked) = std::sync::mpsc::channel::<()>();
+        let blocker = scheduler.spawn(move || blocked.recv().unwrap());
+        while scheduler.queue_depth(TaskPriority::Normal) > 0 {
+            std::thread::yield_now();
+        }
+
+        let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let priorities = [
+            TaskPriority::Low,
+            TaskPriority::Normal,
+            TaskPriority::High,
+            TaskPriority::Critical,
+        ];
+        let handles: Vec<_> = priorities
+            .into_iter()
+            .map(|priority| {
+                let order = Arc::clone(&order);
+                let mut task = Task::from_fn(0, move || order.lock().unwrap().push(priority));
+                task.priority = priority;
+                scheduler.submit(task)
+            })
+            .collect();
+        for priority in priorities {
+            assert_eq!(scheduler.queue_depth(priority), 1);
+        }
+
+        release.send(()).unwrap();
+        blocker.wait().unwrap();
+        handles.into_iter().for_each(|h| h.wait().unwrap());
+        assert_eq!(scheduler.queue_depth(TaskPriority::Low), 0);
+        Arc::try_unwrap(order).unwrap().into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_priority_order_and_aging() {
+        use TaskPriority::*;
+        assert_eq!(
+            run_by_priority(Duration::from_secs(60)),
+            [Critical, High, Normal, Low]
+        );
+        assert_eq!(
+            run_by_priority(Duration::ZERO),
+            [Critical, Low, High, Normal]
+        );
     }
 
-    /// Wait for a task to complete
-    pub fn wait(&self, handle: TaskHandle) -> TaskResult {
-        // TODO: actual wait logic
-        TaskResult::Success
+    #[test]
+    fn test_cancel_and_deadlines() {
+        let scheduler = Scheduler::new(SchedulerConfig {
+            worker_threads: 1,
+            ..SchedulerConfig::default()
+        });
+        let (release, blocked) = std::sync::mpsc::channel::<()>();
+        let blocker = scheduler.spawn(move || blocked.recv().unwrap());
+
+        // Queued behind the blocker: dropped, and its dependents with it
+        let queued = scheduler.spawn(|| 1);
+        let dependent = scheduler.submit(Task::from_fn(2, || 2).after(&queued));
+        assert_eq!(
+            queued.wait_timeout(Duration::from_millis(5)),
+            TaskResult::Pending
+        );
+        assert!(queued.cancel());
+        assert_eq!(queued.wait(), Err(TaskResult::Cancelled));
+        assert_eq!(dependent.wait(), Err(TaskResult::Cancelled));
+
+        // Queued past its deadline: timed out while the worker is busy
+        let expired =
+            scheduler.submit(Task::from_fn(3, || "late").with_timeout(Duration::from_millis(10)));
+        assert_eq!(expired.wait(), Err(TaskResult::TimedOut));
+        release.send(()).unwrap();
+        assert_eq!(blocker.wait(), Ok(()));
+
+        // Running past its deadline: reported without waiting for the call to return
+        let (release, blocked) = std::sync::mpsc::channel::<()>();
+        let stuck = scheduler.submit(
+            Task::from_fn(6, move || blocked.recv().unwrap())
+                .with_timeout(Duration::from_millis(10)),
+        );
+        assert_eq!(stuck.wait(), Err(TaskResult::TimedOut));
+        release.send(()).unwrap();
+
+        // Running cooperative tasks see the token
+        let (started_tx, started) = std::sync::mpsc::channel();
+        let spinning = scheduler.submit(Task::cancellable(4, move |token| {
+            started_tx.send(()).unwrap();
+            while !token.is_cancelled() {
+                std::thread::yield_now();
+            }
+        }));
+        started.recv().unwrap();
+        assert!(!spinning.cancel());
+        assert_eq!(spinning.wait(), Err(TaskResult::Cancelled));
+
+        let overrun = scheduler.submit(
+            Task::cancellable(5, |token| {
+                while !token.is_cancelled() {
+                    std::thread::yield_now();
+                }
+            })
+            .with_timeout(Duration::from_millis(10)),
+        );
+        assert_eq!(overrun.wait(), Err(TaskResult::TimedOut));
+
+        // Plain closures finish normally even if cancelled while running
+        let (started_tx, started) = std::sync::mpsc::channel();
+        let (release, blocked) = std::sync::mpsc::channel::<()>();
+        let plain = scheduler.spawn(move || {
+            started_tx.send(()).unwrap();
+            blocked.recv().unwrap();
+            "done"
+        });
+        started.recv().unwrap();
+        assert!(!plain.cancel());
+        release.send(()).unwrap();
+        assert_eq!(plain.wait(), Ok("done"));
+
+        // A running task over borrowed buffers keeps them until the call returns
+        let borrowed = TaskState::borrowed();
+        assert!(borrowed.claim());
+        borrowed.time_out();
+        assert_eq!(borrowed.result(), TaskResult::Pending);
+        assert!(borrowed.expired());
+
+        // Finished tasks withdraw their deadline timers
+        let quick = scheduler.submit(Task::from_fn(8, || 8).with_timeout(Duration::from_secs(60)));
+        assert_eq!(quick.wait(), Ok(8));
+        assert_eq!(scheduler.watchdog.pending(), 0);
+    }
+
+    /// Minimal executor: park the thread until the waker fires
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        use std::task::{Context, Poll, Wake, Waker};
+
+        struct Unpark(std::thread::Thread);
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_async_handles() {
+        let scheduler = Scheduler::new(SchedulerConfig {
+            worker_threads: 2,
+            ..SchedulerConfig::default()
+        });
+        let (release, blocked) = std::sync::mpsc::channel::<()>();
+        let slow = scheduler.spawn(move || blocked.recv().map(|_| 6));
+        let quick: Vec<_> = (0..4u64).map(|i| scheduler.spawn(move || i * i)).collect();
+
+        // Submitted before it is ever polled, and detached from the scheduler
+        fn detached<F: std::future::Future + Send + 'static>(future: F) -> F {
+            future
+        }
+        let ready = detached(scheduler.submit_async(Task::from_fn(9, || "ready")));
+        assert_eq!(
+            ready.wait_timeout(Duration::from_secs(5)),
+            TaskResult::Success
+        );
+
+        block_on(async {
+            assert_eq!(ready.await, Ok("ready"));
+            let squares: Vec<_> = join_all(quick)
+                .await
+                .into_iter()
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(squares, [0, 1, 4, 9]);
+
+            release.send(()).unwrap();
+            assert_eq!(slow.await, Ok(Ok(6)));
+        });
+    }
+
+    #[test]
+    fn test_task_graph() {
+        let scheduler = Scheduler::new(SchedulerConfig {
+            worker_threads: 3,
+            ..SchedulerConfig::default()
+        });
+        let data = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        // load -> transform -> reduce, plus a failing branch off load
+        let mut graph = TaskGraph::new();
+        let sink = Arc::clone(&data);
+        let load = graph.add(Task::from_fn(1, move || {
+            sink.lock().unwrap().extend(1..=4u32)
+        }));
+        let sink = Arc::clone(&data);
+        let transform = graph.add(Task::from_fn(2, move || {
+            sink.lock().unwrap().iter_mut().for_each(|x| *x *= 10)
+        }));
+        let sink = Arc::clone(&data);
+        let reduce = graph.add(Task::from_fn(3, move || {
+            sink.lock().unwrap().iter().sum::<u32>()
+        }));
+        let broken = graph.add(Task::<()>::from_fn(4, || panic!("bad stage")));
+        let downstream = graph.add(Task::from_fn(5, || "unreachable"));
+        graph.depend(transform, load);
+        graph.depend(reduce, transform);
+        graph.depend(broken, load);
+        graph.depend(downstream, broken);
+
+        let mut handles = scheduler.submit_graph(graph).unwrap();
+        assert_eq!(handles.take(reduce).unwrap().wait(), Ok(100));
+        assert_eq!(
+            handles.take(broken).unwrap().wait(),
+            Err(TaskResult::Failed)
+        );
+        assert_eq!(
+            handles.take(downstream).unwrap().wait(),
+            Err(TaskResult::Cancelled)
+        );
+        assert!(handles.take(reduce).is_none());
+
+        // Dependencies on already submitted handles
+        let first = scheduler.spawn(|| 1);
+        let second = scheduler.submit(Task::from_fn(6, || 2).after(&first));
+        assert_eq!(second.wait(), Ok(2));
+        assert_eq!(first.status(), TaskResult::Success);
+
+        let mut cyclic = TaskGraph::new();
+        let a = cyclic.add(Task::from_fn(10, || ()));
+        let b = cyclic.add(Task::from_fn(11, || ()));
+        let c = cyclic.add(Task::from_fn(12, || ()));
+        cyclic.depend(b, a);
+        cyclic.depend(c, b);
+        cyclic.depend(b, c);
+        assert_eq!(cyclic.validate(), Err(GraphError::Cycle(vec![11, 12])));
+        assert!(scheduler.submit_graph(cyclic).is_err());
     }
 }
@@ -0,0 +1,235 @@
+//! Work-stealing thread pool
+//!
+//! Each worker owns a deque: it pushes and pops its own jobs LIFO at the
+//! back, while idle workers steal the oldest jobs from the front. Jobs
+//! submitted from outside the pool, and any non-`Normal` job, go through
+//! shared per-priority queues.
+//!
+//! Workers look for work in this order: `Critical`, `Low` jobs that have
+//! waited past the aging limit, `High`, their own deque, `Normal`, other
+//! workers' deques, and finally the remaining `Low` jobs.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::task::TaskPriority;
+
+pub(crate) type Job = Box<dyn FnOnce() + Send + 'static>;
+
+thread_local! {
+    /// Pool and worker index when the current thread is a pool worker
+    static WORKER: RefCell<Option<(Arc<Shared>, usize)>> = const { RefCell::new(None) };
+}
+
+struct Entry {
+    job: Job,
+    queued_at: Instant,
+}
+
+struct Shared {
+    /// Shared queues, indexed by `TaskPriority`
+    queues: [Mutex<VecDeque<Entry>>; 4],
+    /// Per-worker deques of `Normal` jobs spawned from that worker
+    deques: Vec<Mutex<VecDeque<Job>>>,
+    /// Queued jobs per priority
+    depth: [AtomicUsize; 4],
+    /// Jobs pushed but not yet taken; bumped before the push so it never underflows
+    queued: AtomicUsize,
+    /// How long a `Low` job waits before it is served ahead of `High` and `Normal`
+    aging: Duration,
+    shutdown: AtomicBool,
+    sleep: Mutex<()>,
+    wake: Condvar,
+}
+
+impl Shared {
+    fn current_worker(&self) -> Option<usize> {
+        WORKER.with(|worker| match &*worker.borrow() {
+            Some((pool, index)) if std::ptr::eq(Arc::as_ptr(pool), self) => Some(*index),
+            _ => None,
+        })
+    }
+
+    fn push(&self, job: Job, priority: TaskPriority) {
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        self.depth[priority as usize].fetch_add(1, Ordering::SeqCst);
+        match self.current_worker() {
+            Some(index) if priority == TaskPriority::Normal => {
+                self.deques[index].lock().unwrap().push_back(job)
+            }
+            _ => self.queues[priority as usize]
+                .lock()
+                .unwrap()
+                .push_back(Entry {
+                    job,
+                    queued_at: Instant::now(),
+                }),
+        }
+        // Taking the lock orders this wake-up after a worker's emptiness check
+        drop(self.sleep.lock().unwrap());
+        self.wake.notify_one();
+    }
+
+    fn find_job(&self, index: usize) -> Option<Job> {
+        use TaskPriority::*;
+        let (job, priority) = self
+            .pop(Critical)
+            .or_else(|| self.pop_aged_low())
+            .or_else(|| self.pop(High))
+            .or_else(|| {
+                let own = self.deques[index].lock().unwrap().pop_back();
+                own.map(|job| (job, Normal))
+            })
+            .or_else(|| self.pop(Normal))
+            .or_else(|| self.steal(index))
+            .or_else(|| self.pop(Low))?;
+        self.depth[priority as usize].fetch_sub(1, Ordering::SeqCst);
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+        Some(job)
+    }
+
+    fn pop(&self, priority: TaskPriority) -> Option<(Job, TaskPriority)> {
+        let entry = self.queues[priority as usize].lock().unwrap().pop_front()?;
+        Some((entry.job, priority))
+    }
+
+    fn pop_aged_low(&self) -> Option<(Job, TaskPriority)> {
+        let mut queue = self.queues[TaskPriority::Low as usize].lock().unwrap();
+        if queue.front()?.queued_at.elapsed() < self.aging {
+            return None;
+        }
+        Some((queue.pop_front()?.job, TaskPriority::Low))
+    }
+
+    fn steal(&self, thief: usize) -> Option<(Job, TaskPriority)> {
+        let n = self.deques.len();
+        (1..n)
+            .map(|k| (thief + k) % n)
+            .find_map(|victim| self.deques[victim].lock().unwrap().pop_front())
+            .map(|job| (job, TaskPriority::Normal))
+    }
+}
+
+/// Run a job, containing any panic that escapes it
+///
+/// Jobs finish their own task state; a stray panic must neither kill the
+/// worker nor unwind into a task that was only helping while it waited.
+fn run(job: Job) {
+    let _ = panic::catch_unwind(AssertUnwindSafe(job));
+}
+
+fn worker_loop(shared: Arc<Shared>, index: usize) {
+    WORKER.with(|worker| *worker.borrow_mut() = Some((Arc::clone(&shared), index)));
+    loop {
+        if let Some(job) = shared.find_job(index) {
+            run(job);
+            continue;
+        }
+        let guard = shared.sleep.lock().unwrap();
+        if shared.queued.load(Ordering::SeqCst) > 0 {
+            continue;
+        }
+        // Queued jobs are drained before exiting so no waiter is left hanging
+        if shared.shutdown.load(Ordering::SeqCst) {
+            break;
+        }
+        drop(shared.wake.wait(guard).unwrap());
+    }
+    WORKER.with(|worker| *worker.borrow_mut() = None);
+}
+
+/// Fixed-size pool of worker threads with per-worker deques
+pub(crate) struct ThreadPool {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+    pub fn new(threads: usize, aging: Duration) -> Self {
+        let threads = threads.max(1);
+        let shared = Arc::new(Shared {
+            queues: Default::default(),
+            deques: (0..threads).map(|_| Mutex::new(VecDeque::new())).collect(),
+            depth: Default::default(),
+            queued: AtomicUsize::new(0),
+            aging,
+            shutdown: AtomicBool::new(false),
+            sleep: Mutex::new(()),
+            wake: Condvar::new(),
+        });
+        let workers = (0..threads)
+            .map(|index| {
+                let shared = Arc::clone(&shared);
+                thread::Builder::new()
+                    .name(format!("super-c-worker-{index}"))
+                    .spawn(move || worker_loop(shared, index))
+                    .expect("failed to spawn scheduler worker")
+            })
+            .collect();
+        Self { shared, workers }
+    }
+
+    /// Queue a job; `Normal` jobs spawned from a worker stay on its deque
+    pub fn spawn(&self, job: Job, priority: TaskPriority) {
+        self.shared.push(job, priority);
+    }
+
+    /// Jobs waiting at `priority`
+    pub fn depth(&self, priority: TaskPriority) -> usize {
+        self.shared.depth[priority as usize].load(Ordering::SeqCst)
+    }
+
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Detached handle for queueing jobs from completion callbacks
+    pub fn spawner(&self) -> Spawner {
+        Spawner(Arc::clone(&self.shared))
+    }
+}
+
+/// Queues jobs on a pool without owning it
+pub(crate) struct Spawner(Arc<Shared>);
+
+impl Spawner {
+    pub fn spawn(&self, job: Job, priority: TaskPriority) {
+        self.0.push(job, priority);
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        drop(self.shared.sleep.lock().unwrap());
+        self.shared.wake.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Whether the calling thread is a worker of any pool
+pub(crate) fn is_worker() -> bool {
+    WORKER.with(|worker| worker.borrow().is_some())
+}
+
+/// Run one queued job from the calling worker's pool, so blocking waits
+/// inside tasks keep the pool moving. Returns false off-pool or when idle.
+pub(crate) fn help() -> bool {
+    let Some((shared, index)) = WORKER.with(|worker| worker.borrow().clone()) else {
+        return false;
+    };
+    match shared.find_job(index) {
+        Some(job) => {
+            run(job);
+            true
+        }
+        None => false,
+    }
+}
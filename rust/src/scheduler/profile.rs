@@ -0,0 +1,338 @@
+//! Measured dispatch profile
+//!
+//! Native executions are timed per (task kind, size bucket, target). For
+//! `TaskTarget::Auto` the fastest measured target wins; targets with too
+//! few attempts in a bucket are tried first, so crossover points are
+//! learned on the machine itself. Failed runs count as attempts, and a
+//! target that fails more often than it succeeds is no longer chosen.
+//! The profile round-trips through a plain-text file so it survives
+//! restarts.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::dispatch::{DispatchPolicy, Workload};
+use crate::ffi::DispatchTarget;
+
+/// Attempts per target and bucket before measurements are trusted
+pub const PROFILE_MIN_SAMPLES: u64 = 3;
+
+const HEADER: &str = "# super-c dispatch profile v2";
+/// Weight of the newest sample in the running mean
+pub(super) const SMOOTHING: f64 = 0.2;
+
+/// Power-of-two size bucket: bucket `b` holds sizes in `[2^(b-1), 2^b)`
+pub fn size_bucket(size: usize) -> u32 {
+    usize::BITS - size.leading_zeros()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Key {
+    kind: u32,
+    bucket: u32,
+    target: DispatchTarget,
+}
+
+/// Timing summary for one (kind, bucket, target)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TargetTiming {
+    /// Successful, timed runs
+    pub samples: u64,
+    pub failures: u64,
+    /// Exponentially smoothed execution time of successful runs in nanoseconds
+    pub mean_ns: f64,
+}
+
+impl TargetTiming {
+    fn attempts(&self) -> u64 {
+        self.samples + self.failures
+    }
+
+    /// Measured, and failing no more often than it succeeds
+    fn usable(&self) -> bool {
+        self.samples > 0 && self.failures <= self.samples
+    }
+}
+
+/// Learned execution times, shared by the scheduler's workers
+#[derive(Debug, Default)]
+pub struct DispatchProfile {
+    timings: Mutex<HashMap<Key, TargetTiming>>,
+}
+
+impl DispatchProfile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record one successful execution of a `kind` task of `size` bytes
+    pub fn record(&self, kind: u32, size: usize, target: DispatchTarget, elapsed: Duration) {
+        let key = Key {
+            kind,
+            bucket: size_bucket(size),
+            target,
+        };
+        let ns = elapsed.as_nanos() as f64;
+        let mut timings = self.timings.lock().unwrap();
+        let timing = timings.entry(key).or_default();
+        timing.samples += 1;
+        if timing.samples == 1 {
+            timing.mean_ns = ns;
+        } else {
+            timing.mean_ns += SMOOTHING * (ns - timing.mean_ns);
+        }
+    }
+
+    /// Record one failed execution of a `kind` task of `size` bytes
+    pub fn record_failure(&self, kind: u32, size: usize, target: DispatchTarget) {
+        let key = Key {
+            kind,
+            bucket: size_bucket(size),
+            target,
+        };
+        self.timings
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_default()
+            .failures += 1;
+    }
+
+    pub fn timing(&self, kind: u32, size: usize, target: DispatchTarget) -> Option<TargetTiming> {
+        let key = Key {
+            kind,
+            bucket: size_bucket(size),
+            target,
+        };
+        self.timings.lock().unwrap().get(&key).copied()
+    }
+
+    /// Sizes at which the fastest measured target for `kind` changes, as
+    /// (lower bound of the bucket, new best target), smallest first
+    pub fn crossovers(&self, kind: u32) -> Vec<(usize, DispatchTarget)> {
+        let timings = self.timings.lock().unwrap();
+        let mut best: HashMap<u32, (DispatchTarget, f64)> = HashMap::new();
+        for (key, timing) in timings.iter() {
+            if key.kind != kind || timing.samples < PROFILE_MIN_SAMPLES || !timing.usable() {
+                continue;
+            }
+            let entry = best
+                .entry(key.bucket)
+                .or_insert((key.target, timing.mean_ns));
+            if timing.mean_ns < entry.1 {
+                *entry = (key.target, timing.mean_ns);
+            }
+        }
+        let mut buckets: Vec<_> = best.into_iter().collect();
+        buckets.sort_by_key(|(bucket, _)| *bucket);
+        let mut crossovers: Vec<(usize, DispatchTarget)> = Vec::new();
+        for (bucket, (target, _)) in buckets {
+            if crossovers.last().map(|(_, t)| *t) != Some(target) {
+                let size = if bucket == 0 {
+                    0
+                } else {
+                    1usize << (bucket - 1)
+                };
+                crossovers.push((size, target));
+            }
+        }
+        crossovers
+    }
+
+    /// Write the profile as text, one `kind bucket target samples failures mean_ns`
+    /// line per entry
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let timings = self.timings.lock().unwrap();
+        let mut entries: Vec<_> = timings.iter().collect();
+        entries.sort_by_key(|(key, _)| (key.kind, key.bucket, key.target as i32));
+        let mut text = String::from(HEADER);
+        text.push('\n');
+        for (key, timing) in entries {
+            text.push_str(&format!(
+                "{} {} {} {} {} {:.1}\n",
+                key.kind,
+                key.bucket,
+                key.target as i32,
+                timing.samples,
+                timing.failures,
+                timing.mean_ns
+            ));
+        }
+        fs::write(path, text)
+    }
+
+    /// Read a profile written by `save`
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let invalid = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("bad dispatch profile line: {line:?}"),
+            )
+        };
+        let mut lines = text.lines();
+        match lines.next() {
+            Some(HEADER) => {}
+            other => return Err(invalid(other.unwrap_or_default())),
+        }
+        let mut timings = HashMap::new();
+        for line in lines.filter(|line| !line.trim().is_empty()) {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let [kind, bucket, target, samples, failures, mean_ns] = fields[..] else {
+                return Err(invalid(line));
+            };
+            let target = match target {
+                "0" => DispatchTarget::Cpu,
+                "1" => DispatchTarget::CpuAsm,
+                "2" => DispatchTarget::Gpu,
+                _ => return Err(invalid(line)),
+            };
+            let key = Key {
+                kind: kind.parse().map_err(|_| invalid(line))?,
+                bucket: bucket.parse().map_err(|_| invalid(line))?,
+                target,
+            };
+            let timing = TargetTiming {
+                samples: samples.parse().map_err(|_| invalid(line))?,
+                failures: failures.parse().map_err(|_| invalid(line))?,
+                mean_ns: mean_ns.parse().map_err(|_| invalid(line))?,
+            };
+            timings.insert(key, timing);
+        }
+        Ok(Self {
+            timings: Mutex::new(timings),
+        })
+    }
+}
+
+impl DispatchPolicy for DispatchProfile {
+    /// Fastest usable candidate, after each has been tried enough
+    fn choose(&self, workload: &Workload, candidates: &[DispatchTarget]) -> DispatchTarget {
+        let timings = self.timings.lock().unwrap();
+        let measured: Vec<_> = candidates
+            .iter()
+            .map(|&target| {
+                let key = Key {
+                    kind: workload.kind,
+                    bucket: size_bucket(workload.bytes),
+                    target,
+                };
+                (target, timings.get(&key).copied())
+            })
+            .collect();
+        // Explore the least-tried target until every candidate is trusted
+        let (explore, attempts) = measured
+            .iter()
+            .map(|(target, timing)| (*target, timing.map_or(0, |t| t.attempts())))
+            .min_by_key(|(_, attempts)| *attempts)
+            .unwrap();
+        if attempts < PROFILE_MIN_SAMPLES {
+            return explore;
+        }
+        // When every candidate keeps failing, rotate through them
+        measured
+            .into_iter()
+            .filter_map(|(target, timing)| {
+                timing
+                    .filter(TargetTiming::usable)
+                    .map(|t| (target, t.mean_ns))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(explore, |(target, _)| target)
+    }
+
+    fn observe(&self, workload: &Workload, target: DispatchTarget, elapsed: Duration) {
+        self.record(workload.kind, workload.bytes, target, elapsed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::{Scheduler, SchedulerConfig, Task, TaskTarget};
+
+    #[test]
+    fn test_learn_and_persist() {
+        let profile = DispatchProfile::new();
+        let mut task = Task::new(1, TaskTarget::Auto);
+        task.kernel_id = 7;
+        task.data_size = 4 << 20;
+
+        // Unmeasured targets are explored before any measurement is trusted
+        assert_eq!(
+            profile.select(&Workload::of(&task), true, false),
+            DispatchTarget::Cpu
+        );
+        let us = Duration::from_micros;
+        for _ in 0..PROFILE_MIN_SAMPLES {
+            profile.record(7, 256, DispatchTarget::Cpu, us(2));
+            profile.record(7, 256, DispatchTarget::Gpu, us(40));
+            profile.record(7, 4 << 20, DispatchTarget::Cpu, us(900));
+        }
+        assert_eq!(
+            profile.select(&Workload::of(&task), true, false),
+            DispatchTarget::Gpu
+        );
+        for _ in 0..PROFILE_MIN_SAMPLES {
+            profile.record(7, 4 << 20, DispatchTarget::Gpu, us(150));
+        }
+        assert_eq!(
+            profile.select(&Workload::of(&task), true, false),
+            DispatchTarget::Gpu
+        );
+        task.data_size = 300;
+        assert_eq!(
+            profile.select(&Workload::of(&task), true, false),
+            DispatchTarget::Cpu
+        );
+        // Without a GPU there is nothing to choose between
+        assert_eq!(
+            profile.select(&Workload::of(&task), false, false),
+            DispatchTarget::Cpu
+        );
+        assert_eq!(
+            profile.crossovers(7),
+            [(256, DispatchTarget::Cpu), (4 << 20, DispatchTarget::Gpu)]
+        );
+
+        // A target that only fails is explored, then passed over
+        task.kernel_id = 8;
+        for _ in 0..PROFILE_MIN_SAMPLES {
+            profile.record(8, 300, DispatchTarget::Cpu, us(5));
+            profile.record_failure(8, 300, DispatchTarget::Gpu);
+        }
+        assert_eq!(
+            profile.select(&Workload::of(&task), true, false),
+            DispatchTarget::Cpu
+        );
+        task.kernel_id = 7;
+
+        let path = std::env::temp_dir().join(format!("sc-profile-{}.txt", std::process::id()));
+        profile.save(&path).unwrap();
+        let reloaded = DispatchProfile::load(&path).unwrap();
+
+        // A scheduler can be asked to refuse a corrupt profile
+        std::fs::write(&path, "# super-c dispatch profile v2\n7 9 x\n").unwrap();
+        let config = || SchedulerConfig {
+            worker_threads: 1,
+            profile_path: Some(path.clone()),
+            ..SchedulerConfig::default()
+        };
+        let err = Scheduler::try_new(config()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        drop(Scheduler::new(config()));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(reloaded.crossovers(7), profile.crossovers(7));
+        assert_eq!(
+            reloaded
+                .timing(7, 4 << 20, DispatchTarget::Gpu)
+                .unwrap()
+                .samples,
+            PROFILE_MIN_SAMPLES
+        );
+    }
+}
@@ -1,9 +1,18 @@
 //! Task definitions and types
 
+use std::any::Any;
 use std::ffi::c_void;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::Waker;
+use std::time::{Duration, Instant};
+
+use super::pool;
 
 /// Task priority levels
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum TaskPriority {
     Low,
     Normal,
@@ -24,31 +33,492 @@ pub enum TaskTarget {
     Auto,
 }
 
+/// Where a task's input data lives
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Residency {
+    /// Host memory: GPU execution pays for the upload and download
+    #[default]
+    Host,
+    /// Already on the device: CPU execution pays for the download
+    Device,
+}
+
+/// Cooperative cancellation flag handed to `Task::cancellable` bodies
+///
+/// Set by `TaskHandle::cancel` or when the task's deadline passes.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+/// Closure body of a task, type-erased until its handle downcasts the output
+pub(crate) type TaskFn = Box<dyn FnOnce(&CancellationToken) -> Box<dyn Any + Send> + Send>;
+
+/// Input and output owned by a native task; its worker holds them until
+/// the native call returns, whatever the handle has been told meanwhile
+pub(crate) struct NativeBuffers {
+    pub input: Vec<u8>,
+    pub output: Vec<u8>,
+}
+
 /// A unit of work to be scheduled
-pub struct Task {
+///
+/// Rust work is a closure (`Task::from_fn`) whose return value comes back
+/// through `TaskHandle<T>`. Without a closure the task is a native workload,
+/// either over buffers it owns (`Task::native`) or, as an unsafe exception,
+/// over caller buffers attached with `Task::with_buffers`.
+pub struct Task<T = ()> {
     pub id: u64,
     pub priority: TaskPriority,
     pub target: TaskTarget,
-    pub data: *const c_void,
-    pub data_size: usize,
+    pub(crate) data: *const c_void,
+    pub(crate) data_size: usize,
+    pub(crate) output: *mut c_void,
+    pub(crate) output_size: usize,
+    /// Kernel launched when the task runs on the GPU
+    pub kernel_id: u32,
+    /// Past this instant the task is dropped, or reported as `TimedOut`
+    /// if it is already running (with borrowed buffers, once its native
+    /// call returns)
+    pub deadline: Option<Instant>,
+    /// Where `data` lives, for transfer-aware dispatch
+    pub residency: Residency,
+    /// Elementwise native kernel with output the size of its input, so it
+    /// may be merged with queued tasks of the same kernel and target
+    pub batchable: bool,
+    /// Set by `Task::native`; `data` and `output` point into these
+    pub(crate) buffers: Option<NativeBuffers>,
+    pub(crate) body: Option<TaskFn>,
+    /// Built by `cancellable`: a cancelled token means the body stopped early
+    pub(crate) cooperative: bool,
+    /// Tasks that must succeed before this one is released
+    pub(crate) deps: Vec<Arc<TaskState>>,
+    _output: PhantomData<fn() -> T>,
 }
 
+// The raw buffers are only touched by the worker that runs the task
+unsafe impl<T> Send for Task<T> {}
+
 impl Task {
+    /// Native task with no buffers yet; see `with_buffers`
     pub fn new(id: u64, target: TaskTarget) -> Self {
+        Self::with_body(id, target, None)
+    }
+
+    /// Attach borrowed input and output buffers to a native task
+    ///
+    /// Prefer `Task::native`. Because the caller owns these buffers, a task
+    /// that overruns its deadline is reported `TimedOut` only once its
+    /// native call has returned, so a hung call also hangs its waiters.
+    ///
+    /// A null `output` makes the worker supply a scratch buffer of
+    /// `data_size` bytes.
+    ///
+    /// # Safety
+    /// `data` must be valid for reads of `data_size` bytes and a non-null
+    /// `output` valid for writes of `output_size` bytes. Both must stay
+    /// valid, and `output` otherwise unused, until the task has finished,
+    /// which its handle reports as any result other than `Pending`.
+    pub unsafe fn with_buffers(
+        mut self,
+        data: *const c_void,
+        data_size: usize,
+        output: *mut c_void,
+        output_size: usize,
+    ) -> Self {
+        self.data = data;
+        self.data_size = data_size;
+        self.output = output;
+        self.output_size = output_size;
+        self
+    }
+
+    pub fn data_size(&self) -> usize {
+        self.data_size
+    }
+
+    pub fn output_size(&self) -> usize {
+        self.output_size
+    }
+}
+
+impl Task<Vec<u8>> {
+    /// Native task that owns `input`; up to `output_size` bytes of output
+    /// come back through its handle
+    ///
+    /// The worker keeps both buffers alive until the native call returns,
+    /// so an overrunning task is reported `TimedOut` at its deadline.
+    pub fn native(id: u64, target: TaskTarget, input: Vec<u8>, output_size: usize) -> Self {
+        let mut buffers = NativeBuffers {
+            input,
+            output: vec![0; output_size],
+        };
+        Self {
+            data: buffers.input.as_ptr().cast(),
+            data_size: buffers.input.len(),
+            output: buffers.output.as_mut_ptr().cast(),
+            output_size,
+            // Moving the vectors does not move their heap storage
+            buffers: Some(buffers),
+            ..Self::with_body(id, target, None)
+        }
+    }
+}
+
+impl<T: Send + 'static> Task<T> {
+    /// Task running `f` on a CPU worker; `f`'s return value is the output
+    pub fn from_fn<F>(id: u64, f: F) -> Self
+    where
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let body: TaskFn = Box::new(move |_| Box::new(f()) as Box<dyn Any + Send>);
+        Self::with_body(id, TaskTarget::Cpu, Some(body))
+    }
+
+    /// Like `from_fn`, but `f` can poll a token to stop early once the task
+    /// is cancelled or overruns its deadline
+    pub fn cancellable<F>(id: u64, f: F) -> Self
+    where
+        F: FnOnce(&CancellationToken) -> T + Send + 'static,
+    {
+        let body: TaskFn = Box::new(move |token| Box::new(f(token)) as Box<dyn Any + Send>);
+        Self {
+            cooperative: true,
+            ..Self::with_body(id, TaskTarget::Cpu, Some(body))
+        }
+    }
+}
+
+impl<T> Task<T> {
+    fn with_body(id: u64, target: TaskTarget, body: Option<TaskFn>) -> Self {
         Self {
             id,
             priority: TaskPriority::Normal,
             target,
             data: std::ptr::null(),
             data_size: 0,
+            output: std::ptr::null_mut(),
+            output_size: 0,
+            kernel_id: 0,
+            deadline: None,
+            residency: Residency::Host,
+            batchable: false,
+            buffers: None,
+            body,
+            cooperative: false,
+            deps: Vec::new(),
+            _output: PhantomData,
+        }
+    }
+
+    /// Whether this is a raw native workload rather than a closure
+    pub fn is_native(&self) -> bool {
+        self.body.is_none()
+    }
+
+    /// Native task over caller-owned buffers
+    pub(crate) fn borrows_buffers(&self) -> bool {
+        self.is_native() && self.buffers.is_none()
+    }
+
+    /// Output of a successful native run: the owned output buffer, if any
+    pub(crate) fn native_output(&mut self) -> Box<dyn Any + Send> {
+        match self.buffers.take() {
+            Some(buffers) => Box::new(buffers.output),
+            None => Box::new(()),
+        }
+    }
+
+    /// Set the deadline `timeout` from now
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.deadline = Some(Instant::now() + timeout);
+        self
+    }
+
+    /// Hold this task back until `handle`'s task succeeds; if that task
+    /// fails or is cancelled, this one is cancelled too
+    pub fn after<U>(mut self, handle: &TaskHandle<U>) -> Self {
+        self.deps.push(Arc::clone(&handle.state));
+        self
+    }
+
+    /// Reinterpret the output type; the body itself is already type-erased
+    pub(crate) fn cast<U>(self) -> Task<U> {
+        Task {
+            id: self.id,
+            priority: self.priority,
+            target: self.target,
+            data: self.data,
+            data_size: self.data_size,
+            output: self.output,
+            output_size: self.output_size,
+            kernel_id: self.kernel_id,
+            deadline: self.deadline,
+            residency: self.residency,
+            batchable: self.batchable,
+            buffers: self.buffers,
+            body: self.body,
+            cooperative: self.cooperative,
+            deps: self.deps,
+            _output: PhantomData,
+        }
+    }
+}
+
+/// Callback run once a task has finished
+pub(crate) type Continuation = Box<dyn FnOnce(TaskResult) + Send>;
+
+struct Completion {
+    result: TaskResult,
+    output: Option<Box<dyn Any + Send>>,
+    continuations: Vec<Continuation>,
+    /// Task awaiting this one through `Future`
+    waker: Option<Waker>,
+}
+
+/// Completion slot shared between a running task and its handle
+pub(crate) struct TaskState {
+    completion: Mutex<Completion>,
+    done: Condvar,
+    /// Set once a worker starts the task, or once it is cancelled or timed out
+    claimed: AtomicBool,
+    pub token: CancellationToken,
+    /// The task runs native code over the submitter's buffers, so an
+    /// overrun is reported only once the native call has returned
+    borrowed: bool,
+    /// The deadline passed while the native call was running
+    expired: AtomicBool,
+}
+
+impl TaskState {
+    pub fn new() -> Self {
+        Self::with_kind(false)
+    }
+
+    /// State of a native task over borrowed buffers; see `time_out`
+    pub fn borrowed() -> Self {
+        Self::with_kind(true)
+    }
+
+    fn with_kind(borrowed: bool) -> Self {
+        Self {
+            completion: Mutex::new(Completion {
+                result: TaskResult::Pending,
+                output: None,
+                continuations: Vec::new(),
+                waker: None,
+            }),
+            done: Condvar::new(),
+            claimed: AtomicBool::new(false),
+            token: CancellationToken::default(),
+            borrowed,
+            expired: AtomicBool::new(false),
+        }
+    }
+
+    /// Claim the task for running or dropping; only the first caller wins
+    pub fn claim(&self) -> bool {
+        !self.claimed.swap(true, Ordering::AcqRel)
+    }
+
+    /// Deadline expiry: drop the task if queued, abandon it if running
+    ///
+    /// A running task over borrowed buffers is only marked `expired`; its
+    /// worker reports `TimedOut` after the call returns, so waiters cannot
+    /// free buffers the native code is still using.
+    pub fn time_out(&self) {
+        self.token.cancel();
+        if self.claim() || !self.borrowed {
+            self.finish(TaskResult::TimedOut, None);
+        } else {
+            self.expired.store(true, Ordering::Release);
+        }
+    }
+
+    /// Whether the deadline passed while the native call was running
+    pub fn expired(&self) -> bool {
+        self.expired.load(Ordering::Acquire)
+    }
+
+    /// Record the outcome and run continuations; only the first call counts
+    pub fn finish(&self, result: TaskResult, output: Option<Box<dyn Any + Send>>) -> bool {
+        let (continuations, waker) = {
+            let mut completion = self.completion.lock().unwrap();
+            if completion.result != TaskResult::Pending {
+                return false;
+            }
+            completion.result = result;
+            completion.output = output;
+            (
+                std::mem::take(&mut completion.continuations),
+                completion.waker.take(),
+            )
+        };
+        // Continuations run first, so a woken waiter sees their effects
+        for continuation in continuations {
+            continuation(result);
+        }
+        self.done.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
         }
+        true
+    }
+
+    /// Run `f` with the outcome once finished, immediately if already done
+    pub fn on_complete(&self, f: Continuation) {
+        let mut completion = self.completion.lock().unwrap();
+        if completion.result == TaskResult::Pending {
+            completion.continuations.push(f);
+        } else {
+            let result = completion.result;
+            drop(completion);
+            f(result);
+        }
+    }
+
+    /// Outcome if finished, otherwise remember `waker` for `finish`
+    pub fn poll_result(&self, waker: &Waker) -> TaskResult {
+        let mut completion = self.completion.lock().unwrap();
+        if completion.result == TaskResult::Pending {
+            match &mut completion.waker {
+                Some(current) if current.will_wake(waker) => {}
+                slot => *slot = Some(waker.clone()),
+            }
+        }
+        completion.result
+    }
+
+    pub fn result(&self) -> TaskResult {
+        self.completion.lock().unwrap().result
+    }
+
+    pub fn take_output(&self) -> Option<Box<dyn Any + Send>> {
+        self.completion.lock().unwrap().output.take()
+    }
+
+    /// Block until the task finishes or `timeout` runs out (then `Pending`)
+    pub fn wait_timeout(&self, timeout: Duration) -> TaskResult {
+        let guard = self.completion.lock().unwrap();
+        let (guard, _) = self
+            .done
+            .wait_timeout_while(guard, timeout, |c| c.result == TaskResult::Pending)
+            .unwrap();
+        guard.result
+    }
+
+    pub fn wait(&self) -> TaskResult {
+        let guard = self.completion.lock().unwrap();
+        self.done
+            .wait_while(guard, |c| c.result == TaskResult::Pending)
+            .unwrap()
+            .result
     }
 }
 
-/// Handle to a submitted task
-#[derive(Debug, Clone, Copy)]
-pub struct TaskHandle {
+impl fmt::Debug for TaskState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaskState")
+            .field("result", &self.result())
+            .finish()
+    }
+}
+
+/// Handle to a submitted task, yielding its `T` output on success
+pub struct TaskHandle<T = ()> {
     pub id: u64,
+    pub(crate) state: Arc<TaskState>,
+    _output: PhantomData<fn() -> T>,
+}
+
+impl<T> TaskHandle<T> {
+    pub(crate) fn new(id: u64, state: Arc<TaskState>) -> Self {
+        Self {
+            id,
+            state,
+            _output: PhantomData,
+        }
+    }
+
+    /// Current state without blocking; `Pending` until the task finishes
+    pub fn status(&self) -> TaskResult {
+        self.state.result()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.status() != TaskResult::Pending
+    }
+
+    /// Cancel the task; returns true if it was dropped before it started
+    ///
+    /// A running `Task::cancellable` body sees its token flip and finishes
+    /// as `Cancelled`; other running tasks are left to complete.
+    pub fn cancel(&self) -> bool {
+        self.state.token.cancel();
+        self.state.claim() && self.state.finish(TaskResult::Cancelled, None)
+    }
+
+    /// Block for at most `timeout`; `Pending` if the task is still running
+    pub fn wait_timeout(&self, timeout: Duration) -> TaskResult {
+        self.block(Some(Instant::now() + timeout))
+    }
+
+    /// On a worker thread this runs other queued tasks while waiting
+    fn block(&self, until: Option<Instant>) -> TaskResult {
+        if !pool::is_worker() {
+            return match until {
+                Some(until) => self
+                    .state
+                    .wait_timeout(until.saturating_duration_since(Instant::now())),
+                None => self.state.wait(),
+            };
+        }
+        loop {
+            let result = self.status();
+            if result != TaskResult::Pending || until.is_some_and(|t| Instant::now() >= t) {
+                return result;
+            }
+            if !pool::help() {
+                self.state.wait_timeout(Duration::from_millis(1));
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for TaskHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaskHandle")
+            .field("id", &self.id)
+            .field("status", &self.status())
+            .finish()
+    }
+}
+
+impl<T: 'static> TaskHandle<T> {
+    /// Block until the task finishes and take its output
+    pub fn wait(self) -> Result<T, TaskResult> {
+        let result = self.block(None);
+        self.output(result)
+    }
+
+    /// Take the output of a finished task
+    pub(crate) fn output(&self, result: TaskResult) -> Result<T, TaskResult> {
+        match result {
+            TaskResult::Success => {
+                let output = self.state.take_output().expect("task output already taken");
+                Ok(*output.downcast::<T>().expect("task output type mismatch"))
+            }
+            failed => Err(failed),
+        }
+    }
 }
 
 /// Result of task execution
@@ -57,5 +527,6 @@ pub enum TaskResult {
     Success,
     Failed,
     Cancelled,
+    TimedOut,
     Pending,
 }
@@ -0,0 +1,116 @@
+//! Deadline watchdog
+//!
+//! One thread sleeps until the earliest task deadline and times the task
+//! out: queued tasks are dropped, running ones have their cancellation
+//! token set. A running closure is reported as `TimedOut` at once while it
+//! finishes in the background; a running native task borrows its caller's
+//! buffers, so it is reported only once the native call returns. The same
+//! thread fires other scheduler timers, such as batch flushes.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use super::task::TaskState;
+
+/// Timer key: the firing instant, then a sequence number for ties
+type TimerKey = (Instant, u64);
+
+#[derive(Default)]
+struct Queue {
+    timers: BTreeMap<TimerKey, Box<dyn FnOnce() + Send>>,
+    next_seq: u64,
+    shutdown: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    queue: Mutex<Queue>,
+    wake: Condvar,
+}
+
+pub(crate) struct Watchdog {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    pub fn new() -> Self {
+        let shared = Arc::new(Shared::default());
+        let thread = {
+            let shared = Arc::clone(&shared);
+            thread::Builder::new()
+                .name("super-c-watchdog".into())
+                .spawn(move || watch(&shared))
+                .expect("failed to spawn deadline watchdog")
+        };
+        Self {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    /// Time out `state` at `at` unless it has finished by then
+    ///
+    /// The timer is withdrawn as soon as the task finishes, so early
+    /// finishers do not pile up in the queue.
+    pub fn watch(&self, state: &Arc<TaskState>, at: Instant) {
+        let task: Weak<TaskState> = Arc::downgrade(state);
+        let key = self.schedule(at, move || {
+            if let Some(task) = task.upgrade() {
+                task.time_out();
+            }
+        });
+        let shared: Weak<Shared> = Arc::downgrade(&self.shared);
+        state.on_complete(Box::new(move |_| {
+            if let Some(shared) = shared.upgrade() {
+                shared.queue.lock().unwrap().timers.remove(&key);
+            }
+        }));
+    }
+
+    /// Run `action` on the watchdog thread at `at`; keep it short
+    pub fn schedule(&self, at: Instant, action: impl FnOnce() + Send + 'static) -> TimerKey {
+        let mut queue = self.shared.queue.lock().unwrap();
+        let key = (at, queue.next_seq);
+        queue.next_seq += 1;
+        queue.timers.insert(key, Box::new(action));
+        drop(queue);
+        self.shared.wake.notify_one();
+        key
+    }
+
+    /// Timers still waiting to fire
+    #[cfg(test)]
+    pub fn pending(&self) -> usize {
+        self.shared.queue.lock().unwrap().timers.len()
+    }
+}
+
+fn watch(shared: &Shared) {
+    let mut queue = shared.queue.lock().unwrap();
+    while !queue.shutdown {
+        let now = Instant::now();
+        match queue.timers.first_key_value().map(|(&(at, _), _)| at) {
+            Some(at) if at <= now => {
+                let (_, action) = queue.timers.pop_first().unwrap();
+                drop(queue);
+                action();
+                queue = shared.queue.lock().unwrap();
+            }
+            Some(at) => queue = shared.wake.wait_timeout(queue, at - now).unwrap().0,
+            None => queue = shared.wake.wait(queue).unwrap(),
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().shutdown = true;
+        self.shared.wake.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}